@@ -0,0 +1,190 @@
+//! Adjusting the brightness (and optionally the temperature) to the ambient light level,
+//! as reported by an IIO light sensor in sysfs.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cli::AmbientArgs;
+use crate::color::Config;
+use crate::event_loop::EventSender;
+use crate::util::lerp;
+use crate::Event;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// The weight of each new reading in the moving average of the (logarithmic) light level.
+const SMOOTHING: f32 = 0.3;
+/// The light level must move by at least this much (on the `0.0..=1.0` scale) before it is reported.
+const HYSTERESIS: f32 = 0.05;
+
+/// An IIO device with an illuminance channel.
+struct Sensor {
+	directory: PathBuf,
+}
+
+impl Sensor {
+	/// Finds the first device under `root` (usually `/sys/bus/iio/devices`) with an illuminance channel.
+	fn find(root: &Path) -> Option<Self> {
+		let mut directories: Vec<_> = std::fs::read_dir(root)
+			.ok()?
+			.filter_map(|entry| Some(entry.ok()?.path()))
+			.collect();
+		// Be deterministic if there are multiple sensors.
+		directories.sort();
+		directories
+			.into_iter()
+			.find(|directory| directory.join("in_illuminance_raw").exists())
+			.map(|directory| Self { directory })
+	}
+
+	/// Returns the illuminance in lux.
+	fn read(&self) -> Option<f32> {
+		let read_value = |name: &str| -> Option<f32> {
+			std::fs::read_to_string(self.directory.join(name))
+				.ok()?
+				.trim()
+				.parse()
+				.ok()
+		};
+		let raw = read_value("in_illuminance_raw")?;
+		let offset = read_value("in_illuminance_offset").unwrap_or(0.0);
+		let scale = read_value("in_illuminance_scale").unwrap_or(1.0);
+		Some((raw + offset) * scale)
+	}
+}
+
+/// Smooths lux readings and maps them to a light level in the range `0.0..=1.0`,
+/// only reporting changes that are larger than `HYSTERESIS`.
+struct Smoother {
+	dark_lux: f32,
+	bright_lux: f32,
+	/// Moving average of `log10(lux)`.
+	average: Option<f32>,
+	reported: Option<f32>,
+}
+
+impl Smoother {
+	fn update(&mut self, lux: f32) -> Option<f32> {
+		// Light levels are perceived logarithmically.
+		let log_lux = lux.max(f32::MIN_POSITIVE).log10();
+		let average = self
+			.average
+			.map_or(log_lux, |average| lerp(average, log_lux, SMOOTHING));
+		self.average = Some(average);
+
+		let (dark, bright) = (self.dark_lux.log10(), self.bright_lux.log10());
+		let level = ((average - dark) / (bright - dark)).clamp(0.0, 1.0);
+		if self
+			.reported
+			.is_some_and(|reported| (level - reported).abs() < HYSTERESIS)
+		{
+			return None;
+		}
+		self.reported = Some(level);
+		Some(level)
+	}
+}
+
+/// Polls the sensor every `POLL_INTERVAL` and sends `Event::SetAmbientLight` when the light level changes.
+pub struct Monitor {
+	sensor: Sensor,
+	smoother: Smoother,
+	next_poll: Instant,
+}
+
+impl Monitor {
+	pub fn new(args: &AmbientArgs) -> Option<Self> {
+		let Some(sensor) = Sensor::find(&args.iio_root) else {
+			tracing::warn!(root = ?args.iio_root, "no ambient light sensor found");
+			return None;
+		};
+		tracing::info!(directory = ?sensor.directory, "using ambient light sensor");
+
+		Some(Self {
+			sensor,
+			smoother: Smoother {
+				dark_lux: args.ambient_dark_lux,
+				bright_lux: args.ambient_bright_lux,
+				average: None,
+				reported: None,
+			},
+			next_poll: Instant::now(),
+		})
+	}
+
+	pub fn next_poll(&self) -> Instant {
+		self.next_poll
+	}
+
+	pub fn poll(&mut self, event_send: &EventSender) {
+		self.next_poll = Instant::now() + POLL_INTERVAL;
+		if let Some(lux) = self.sensor.read() {
+			if let Some(level) = self.smoother.update(lux) {
+				tracing::debug!(lux, level, "ambient light level changed");
+				_ = event_send.send(Event::SetAmbientLight(level));
+			}
+		} else {
+			tracing::warn!(directory = ?self.sensor.directory, "could not read ambient light sensor");
+		}
+	}
+}
+
+/// Adjusts `config` for the given light level, where 0.0 is dark and 1.0 is bright.
+pub fn adjust(config: Config, level: f32, args: &AmbientArgs) -> Config {
+	let config =
+		config.with_brightness(config.brightness() * lerp(args.ambient_min_brightness, 1.0, level));
+	match args.ambient_dark_temperature {
+		Some(dark_temperature) => {
+			let temperature = config.temperature() as f32;
+			config
+				.with_temperature(lerp(temperature.min(dark_temperature as f32), temperature, level) as u32)
+		}
+		None => config,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::{Sensor, Smoother};
+
+	#[test]
+	fn fake_sysfs() {
+		let root = tempfile::tempdir().unwrap();
+		// An accelerometer, which comes first but has no illuminance channel.
+		let accelerometer = root.path().join("iio:device0");
+		fs::create_dir(&accelerometer).unwrap();
+		fs::write(accelerometer.join("in_accel_x_raw"), "12\n").unwrap();
+		let light = root.path().join("iio:device1");
+		fs::create_dir(&light).unwrap();
+		fs::write(light.join("in_illuminance_raw"), "200\n").unwrap();
+		fs::write(light.join("in_illuminance_scale"), "0.500000\n").unwrap();
+
+		let sensor = Sensor::find(root.path()).unwrap();
+		assert_eq!(sensor.directory, light);
+		assert_eq!(sensor.read(), Some(100.0));
+
+		let mut smoother = Smoother {
+			dark_lux: 5.0,
+			bright_lux: 500.0,
+			average: None,
+			reported: None,
+		};
+		// 100 lux is two thirds of the way from 5 to 500 lux on a logarithmic scale.
+		let level = smoother.update(sensor.read().unwrap()).unwrap();
+		assert!((level - 0.65).abs() < 0.01);
+		// Small changes are not reported.
+		fs::write(light.join("in_illuminance_raw"), "210\n").unwrap();
+		assert_eq!(smoother.update(sensor.read().unwrap()), None);
+		// In the dark, the level falls gradually.
+		fs::write(light.join("in_illuminance_raw"), "2\n").unwrap();
+		let levels: Vec<_> = (0..20)
+			.filter_map(|_| smoother.update(sensor.read().unwrap()))
+			.collect();
+		assert!(levels.windows(2).all(|pair| pair[1] < pair[0]));
+		assert_eq!(levels.last(), Some(&0.0));
+
+		fs::remove_file(light.join("in_illuminance_raw")).unwrap();
+		assert_eq!(sensor.read(), None);
+	}
+}
@@ -0,0 +1,208 @@
+//! Gamma backends, which apply ramps to the outputs of a display stack.
+
+use std::os::fd::BorrowedFd;
+
+use wayland_client::Connection;
+
+use crate::color::{Config, Curves, Ramps};
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::{drm, dry_run, night_light, wlr, x11};
+
+/// Identifies an output within its backend.
+pub type OutputId = u32;
+
+/// An output, as reported by a backend with `Event::AddOutput`.
+#[derive(Debug)]
+pub struct OutputInfo {
+	pub id: OutputId,
+	/// The connector name, e.g., `DP-1`, if known.
+	pub name: Option<Box<str>>,
+	pub description: Box<str>,
+	pub ramp_size: usize,
+}
+
+/// A display stack whose outputs' gamma ramps can be set.
+///
+/// Backends report outputs with `Event::AddOutput` and `Event::RemoveOutput`,
+/// and send `Event::OutputsEnumerated` once the outputs that exist at startup have been added.
+pub trait GammaBackend {
+	/// Handles events that have been read from the display stack.
+	/// The event loop calls this every time before it waits.
+	fn dispatch_pending(&mut self) -> Result<(), Error>;
+
+	/// A file descriptor for the event loop to wait on, if the backend's events do not arrive on the Wayland connection.
+	fn fd(&self) -> Option<BorrowedFd<'_>> {
+		None
+	}
+
+	/// Does nothing if the output has already been removed.
+	fn set_gamma(&mut self, output: OutputId, ramps: &Ramps) -> Result<(), Error>;
+
+	/// Called with the config for the schedule time `time`, before the gamma of the outputs is set.
+	///
+	/// For backends that delegate to a desktop's own night light, which only takes the config's temperature.
+	/// They do not report any outputs, so `set_gamma` is never called.
+	fn set_config(&mut self, _time: time::Time, _config: Config) -> Result<(), Error> {
+		Ok(())
+	}
+
+	/// How often the config has to be applied again even if it did not change.
+	fn update_interval(&self) -> Option<std::time::Duration> {
+		None
+	}
+
+	/// Called after the gamma of all outputs has been set.
+	/// Waits until the display stack has received the ramps, which also shows that it is still alive.
+	fn flush(&mut self) -> Result<(), Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BackendKind {
+	/// `wlr` if connected to a Wayland compositor (or `gnome` or `kde` if it does not support `wlr`),
+	/// `x11` if `DISPLAY` is set, and `drm` otherwise.
+	#[default]
+	Auto,
+	/// The `zwlr_gamma_control_v1` Wayland protocol, supported by wlroots-based compositors.
+	Wlr,
+	/// The `RandR` extension of the X server given by `DISPLAY`.
+	X11,
+	/// Direct access to `/dev/dri/card*`, for when no display server is running.
+	Drm,
+	/// GNOME's night light. Only the temperature is applied.
+	Gnome,
+	/// KDE Plasma's night light. Only the temperature is applied.
+	Kde,
+	/// Print the configs and checksums of the ramps instead of setting the gamma, without connecting to anything.
+	DryRun,
+}
+
+impl BackendKind {
+	/// Whether failing to connect to a Wayland compositor is fatal.
+	pub fn needs_wayland(self) -> bool {
+		match self {
+			// If `WAYLAND_DISPLAY` is set, a compositor is supposed to be running.
+			Self::Auto => {
+				std::env::var_os("WAYLAND_DISPLAY").is_some() && std::env::var_os("DISPLAY").is_none()
+			}
+			Self::Wlr => true,
+			Self::X11 | Self::Drm | Self::Gnome | Self::Kde | Self::DryRun => false,
+		}
+	}
+}
+
+/// Opens the backend, which immediately starts reporting outputs to `event_send`.
+///
+/// `connection` must not be `None` if `kind.needs_wayland()`.
+pub fn open(
+	kind: BackendKind,
+	event_send: EventSender,
+	connection: Option<&Connection>,
+) -> Result<Box<dyn GammaBackend>, Error> {
+	match (kind, connection) {
+		(BackendKind::Wlr, Some(connection)) => {
+			Ok(Box::new(wlr::Backend::new(event_send, connection)?))
+		}
+		(BackendKind::Auto, Some(connection)) => {
+			match wlr::Backend::new(event_send.clone(), connection) {
+				Ok(backend) => {
+					tracing::debug!("using the wlr backend");
+					Ok(Box::new(backend))
+				}
+				// Mutter and KWin do not support the protocol, so fall back to their night lights.
+				Err(error @ Error::MissingProtocol(_)) => {
+					let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+					if desktop.split(':').any(|desktop| desktop == "GNOME") {
+						tracing::debug!("using the gnome backend");
+						Ok(Box::new(night_light::Gnome::new(&event_send)?))
+					} else if desktop.split(':').any(|desktop| desktop == "KDE") {
+						tracing::debug!("using the kde backend");
+						Ok(Box::new(night_light::Kde::new(&event_send)?))
+					} else {
+						Err(error)
+					}
+				}
+				Err(error) => Err(error),
+			}
+		}
+		(BackendKind::Wlr, None) => unreachable!("the wlr backend needs a Wayland connection"),
+		(BackendKind::Auto, None) if std::env::var_os("DISPLAY").is_some() => {
+			tracing::debug!("using the x11 backend");
+			Ok(Box::new(x11::Backend::new(event_send)?))
+		}
+		(BackendKind::X11, _) => Ok(Box::new(x11::Backend::new(event_send)?)),
+		(BackendKind::Gnome, _) => Ok(Box::new(night_light::Gnome::new(&event_send)?)),
+		(BackendKind::Kde, _) => Ok(Box::new(night_light::Kde::new(&event_send)?)),
+		(BackendKind::DryRun, _) => Ok(Box::new(dry_run::Backend::new(&event_send))),
+		(BackendKind::Auto | BackendKind::Drm, _) => {
+			tracing::debug!("using the drm backend");
+			Ok(Box::new(drm::Backend::new(event_send)?))
+		}
+	}
+}
+
+/// An output along with the state needed to set its gamma.
+pub struct Output {
+	info: OutputInfo,
+	ramps: Ramps,
+	/// Applied after the config, e.g. for calibration.
+	curves: Option<Curves>,
+	last_config: Option<Config>,
+}
+
+impl std::fmt::Debug for Output {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		// Ensure exhaustiveness.
+		let Self {
+			info,
+			ramps: _,
+			curves,
+			last_config,
+		} = self;
+
+		f.debug_struct("Output")
+			.field("info", info)
+			.field("has_curves", &curves.is_some())
+			.field("last_config", last_config)
+			.finish_non_exhaustive()
+	}
+}
+
+impl Output {
+	pub fn new(info: OutputInfo) -> Self {
+		Self {
+			ramps: Ramps::new(info.ramp_size),
+			info,
+			curves: None,
+			last_config: None,
+		}
+	}
+
+	pub fn set_gamma(&mut self, backend: &mut dyn GammaBackend, config: Config) -> Result<(), Error> {
+		tracing::trace!(?self.info.description, ?config, "setting gamma");
+
+		let last_config = self.last_config.replace(config);
+		if last_config.is_some_and(|last_config| !config.different_from(last_config)) {
+			tracing::trace!(?self.info.description, new_config=?config, ?last_config, "new config is not different enough from last config");
+		}
+
+		config.generate_ramps(&mut self.ramps, self.curves.as_ref());
+		backend.set_gamma(self.info.id, &self.ramps)
+	}
+
+	/// `key` can be either the output's name (e.g., `DP-1`) or its full description.
+	#[must_use]
+	pub fn matches_output(&self, key: &str) -> bool {
+		self.info.name.as_deref() == Some(key) || *self.info.description == *key
+	}
+
+	pub fn set_curves(&mut self, curves: Option<Curves>) {
+		self.curves = curves;
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn id(&self) -> OutputId {
+		self.info.id
+	}
+}
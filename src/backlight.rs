@@ -0,0 +1,102 @@
+//! Dimming with the hardware backlight, through logind so that no special privileges are needed.
+
+use std::path::Path;
+
+use crate::cli::BacklightArgs;
+use crate::error::Error;
+use crate::session::SessionProxyBlocking;
+
+pub struct Backlight {
+	proxy: SessionProxyBlocking<'static>,
+	/// The name of the device in `/sys/class/backlight`.
+	name: String,
+	max_brightness: u32,
+	/// The brightness when we started, which is treated as full brightness and restored on exit.
+	original_brightness: u32,
+	/// The lowest fraction of `original_brightness` that the backlight is dimmed to.
+	min_fraction: f32,
+	current_brightness: u32,
+}
+
+impl Backlight {
+	/// Returns `None` if there is no usable backlight device.
+	pub fn open(args: &BacklightArgs) -> Result<Option<Self>, Error> {
+		let Some((name, max_brightness, original_brightness)) = Self::find(args) else {
+			return Ok(None);
+		};
+
+		let dbus = zbus::blocking::Connection::system()
+			.map_err(Error::dbus("could not connect to the D-Bus system bus"))?;
+		let proxy = SessionProxyBlocking::new(&dbus)
+			.map_err(Error::dbus("could not connect to the logind session"))?;
+
+		tracing::info!(name, max_brightness, original_brightness, "using backlight");
+
+		Ok(Some(Self {
+			proxy,
+			name,
+			max_brightness,
+			original_brightness,
+			min_fraction: args.backlight_min,
+			current_brightness: original_brightness,
+		}))
+	}
+
+	/// Returns the device's name, maximum brightness, and current brightness.
+	fn find(args: &BacklightArgs) -> Option<(String, u32, u32)> {
+		let directory = if let Some(name) = &args.backlight_device {
+			args.backlight_root.join(name)
+		} else {
+			let mut directories: Vec<_> = std::fs::read_dir(&args.backlight_root)
+				.ok()?
+				.filter_map(|entry| Some(entry.ok()?.path()))
+				.collect();
+			// Be deterministic if there are multiple devices.
+			directories.sort();
+			directories.into_iter().next()?
+		};
+		let name = directory.file_name()?.to_str()?.to_owned();
+		let max_brightness = read_value(&directory, "max_brightness")?;
+		let original_brightness = read_value(&directory, "brightness")?;
+		Some((name, max_brightness, original_brightness))
+	}
+
+	/// Dims the backlight as far as allowed to achieve `brightness`,
+	/// and returns the brightness that the rest of the dimming has to be done with.
+	pub fn apply(&mut self, brightness: f32) -> f32 {
+		let backlight_fraction = brightness.max(self.min_fraction);
+		let level = (self.original_brightness as f32 * backlight_fraction).round() as u32;
+		self.set(level.min(self.max_brightness));
+		if backlight_fraction > 0.0 {
+			brightness / backlight_fraction
+		} else {
+			0.0
+		}
+	}
+
+	fn set(&mut self, level: u32) {
+		if level == self.current_brightness {
+			return;
+		}
+		tracing::trace!(self.name, level, "setting backlight brightness");
+		match self.proxy.set_brightness("backlight", &self.name, level) {
+			Ok(()) => self.current_brightness = level,
+			Err(error) => tracing::warn!(self.name, %error, "could not set backlight brightness"),
+		}
+	}
+}
+
+impl Drop for Backlight {
+	/// Restores the original brightness, including when the daemon exits because of an error.
+	fn drop(&mut self) {
+		self.set(self.original_brightness);
+	}
+}
+
+fn read_value(directory: &Path, name: &str) -> Option<u32> {
+	std::fs::read_to_string(directory.join(name))
+		.ok()?
+		.trim()
+		.parse()
+		.ok()
+}
@@ -0,0 +1,336 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+
+use crate::backend::BackendKind;
+use crate::color::{parse_temperature, parse_tint, Tint, WhitePointModel};
+use crate::inhibit;
+use crate::query::QueryFormat;
+use crate::ramp_file::Format;
+use crate::session::LockBehavior;
+use crate::status::WatchFormat;
+
+/// A blue light filter for Wayland and X11.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+	#[command(subcommand)]
+	pub command: Option<Command>,
+
+	/// How to set the gamma.
+	#[arg(long, value_enum, default_value_t)]
+	pub backend: BackendKind,
+
+	/// The color temperature during the day.
+	///
+	/// Given in Kelvins (`6500`, `6500K`) or mireds (`154mired`).
+	/// Temperatures below 1000K fade towards pure red, which is reached at 500K.
+	#[arg(long, value_parser = parse_temperature, default_value = "6500K")]
+	pub day_temperature: u32,
+	/// The color temperature during the night, in the same format as `--day-temperature`.
+	#[arg(long, value_parser = parse_temperature, default_value = "3500K")]
+	pub night_temperature: u32,
+	/// A tint to use during the day instead of the day temperature.
+	///
+	/// Either per-channel multipliers (`rgb:1.0,0.75,0.4`) or a CIE 1931 chromaticity (`xy:0.48,0.41`).
+	#[arg(long, value_parser = parse_tint)]
+	pub day_tint: Option<Tint>,
+	/// A tint to use during the night instead of the night temperature, in the same format as `--day-tint`.
+	#[arg(long, value_parser = parse_tint)]
+	pub night_tint: Option<Tint>,
+	/// How to determine the white point for a given temperature.
+	#[arg(long, global = true, value_enum, default_value_t)]
+	pub white_point_model: WhitePointModel,
+	/// Load calibration curves from the `vcgt` tag of an ICC profile, given as `OUTPUT=PATH`.
+	///
+	/// `OUTPUT` is either the output's name (e.g., `DP-1`) or its full description.
+	/// Can be given multiple times.
+	#[arg(long = "icc-profile", value_name = "OUTPUT=PATH", value_parser = parse_output_path)]
+	pub icc_profiles: Vec<(String, PathBuf)>,
+	/// Load a base curve from a file written by `rustshift dump-ramps`, given as `OUTPUT=PATH`.
+	///
+	/// The format is chosen by the extension (`.csv`, `.json`, or `.cube`), and all other files are read as raw ramps.
+	/// Like the curves from ICC profiles, the base curve is applied after the temperature and brightness.
+	/// ICC profiles take precedence if both are given for the same output.
+	#[arg(long = "base-curve", value_name = "OUTPUT=PATH", value_parser = parse_output_path)]
+	pub base_curves: Vec<(String, PathBuf)>,
+	/// The brightness when dimmed, from 0 (black) to 1 (full brightness).
+	#[arg(long, value_parser = parse_fraction, default_value_t = 0.4)]
+	pub dim_brightness: f32,
+	/// How long dimming and undimming take, in seconds.
+	#[arg(long, value_parser = parse_non_negative, default_value_t = 1.0)]
+	pub dim_fade: f32,
+	/// Dim after the user has been idle for this many seconds, and undim on activity.
+	/// Idling does not dim while a logind idle inhibitor is active.
+	#[arg(long)]
+	pub idle_timeout: Option<u64>,
+	/// What to do while the session is locked, according to logind.
+	#[arg(long, value_enum, default_value_t)]
+	pub while_locked: LockBehavior,
+	/// Override the schedule while matching windows are focused.
+	/// `RULE` is `CONDITION:ACTION`, where `CONDITION` is `app_id=ID`, `title=SUBSTRING`, or `fullscreen`,
+	/// and `ACTION` is `neutral` (no tint) or `pause` (keep the current temperature).
+	/// Can be given multiple times; the first matching rule applies.
+	#[arg(long, value_name = "RULE", value_parser = str::parse::<inhibit::Rule>)]
+	pub inhibit: Vec<inhibit::Rule>,
+	/// Start with inverted colors. Can be changed at runtime with `rustshift ctl invert`.
+	#[arg(long, global = true)]
+	pub invert: bool,
+	/// Start with muted colors. Can be changed at runtime with `rustshift ctl reduce-color`.
+	#[arg(long, global = true)]
+	pub reduce_color: bool,
+	#[command(flatten)]
+	pub ambient: AmbientArgs,
+	#[command(flatten)]
+	pub backlight: BacklightArgs,
+}
+
+impl Args {
+	/// Checks the combinations of arguments that clap cannot check by itself.
+	pub fn validate(&self) -> Result<(), String> {
+		if self.ambient.ambient_dark_lux >= self.ambient.ambient_bright_lux {
+			return Err("--ambient-dark-lux must be less than --ambient-bright-lux".into());
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct AmbientArgs {
+	/// Adjust the brightness to the ambient light level, as reported by an IIO light sensor.
+	#[arg(id = "ambient_light", long = "ambient-light")]
+	pub enabled: bool,
+	/// Where to look for IIO devices.
+	#[arg(long, default_value = "/sys/bus/iio/devices")]
+	pub iio_root: PathBuf,
+	/// At or below this illuminance (in lux), the brightness is `--ambient-min-brightness`.
+	#[arg(long, value_parser = parse_positive, default_value_t = 5.0)]
+	pub ambient_dark_lux: f32,
+	/// At or above this illuminance (in lux), the brightness is not reduced. Must be greater than `--ambient-dark-lux`.
+	#[arg(long, value_parser = parse_positive, default_value_t = 500.0)]
+	pub ambient_bright_lux: f32,
+	/// The factor that the brightness is multiplied by in the dark.
+	#[arg(long, value_parser = parse_fraction, default_value_t = 0.6)]
+	pub ambient_min_brightness: f32,
+	/// If given, the temperature is lowered towards this temperature in the dark,
+	/// in the same format as `--day-temperature`.
+	#[arg(long, value_parser = parse_temperature)]
+	pub ambient_dark_temperature: Option<u32>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BacklightArgs {
+	/// Dim with the hardware backlight (through logind) before dimming the gamma ramps.
+	///
+	/// The backlight level at startup is treated as full brightness, and is restored on exit.
+	#[arg(id = "backlight", long = "backlight")]
+	pub enabled: bool,
+	/// Where to look for backlight devices.
+	#[arg(long, default_value = "/sys/class/backlight")]
+	pub backlight_root: PathBuf,
+	/// The backlight device to use. Defaults to the first one found.
+	#[arg(long)]
+	pub backlight_device: Option<String>,
+	/// The lowest fraction of the original backlight level to dim to. Any further dimming is done with the gamma ramps.
+	#[arg(long, value_parser = parse_fraction, default_value_t = 0.3)]
+	pub backlight_min: f32,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Send a command to the running daemon.
+	///
+	/// Commands are `dim`, `invert`, `reduce-color`, and `pause`, each followed by `on`, `off`, or `toggle` (the default),
+	/// and `tint` followed by a tint in the same format as `--day-tint` or `off`.
+	Ctl {
+		#[command(subcommand)]
+		command: CtlCommand,
+	},
+	/// Write the ramps for a fixed config to standard output.
+	DumpRamps(DumpRampsArgs),
+	/// Replay the schedule on the outputs at accelerated speed, printing the time and config as it goes.
+	///
+	/// The replay covers the 24 hours from now, after which (or on Ctrl-C) the gamma is reset like when the daemon exits.
+	/// Only one program can set the gamma at a time, so stop the daemon first, and start it again afterwards.
+	Preview(PreviewArgs),
+	/// Draw the temperature and brightness over the day, as a chart in the terminal or as an SVG file.
+	///
+	/// The day is today, in the local time zone. With a tint, its white point is drawn instead of the temperature.
+	Plot(PlotArgs),
+	/// Print the temperature, brightness, period, and surrounding transitions at a given time.
+	///
+	/// The time is in the local time zone, like the daemon's schedule.
+	Query(QueryArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CtlCommand {
+	/// Print the status whenever it changes, e.g., for status bars.
+	Watch {
+		#[arg(long, value_enum, default_value_t)]
+		format: WatchFormat,
+	},
+	#[command(external_subcommand)]
+	Other(Vec<String>),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DumpRampsArgs {
+	/// In the same format as `--day-temperature`.
+	#[arg(long, value_parser = parse_temperature, default_value = "6500K")]
+	pub temperature: u32,
+	/// From 0 (black) to 1 (full brightness).
+	#[arg(long, value_parser = parse_fraction, default_value_t = 1.0)]
+	pub brightness: f32,
+	/// Used instead of the temperature, in the same format as `--day-tint`.
+	#[arg(long, value_parser = parse_tint)]
+	pub tint: Option<Tint>,
+	/// The number of entries in each ramp.
+	#[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(2..))]
+	pub size: u32,
+	#[arg(long, value_enum, default_value_t = Format::Csv)]
+	pub format: Format,
+	/// Apply calibration curves from the `vcgt` tag of an ICC profile.
+	#[arg(long, conflicts_with = "base_curve")]
+	pub icc_profile: Option<PathBuf>,
+	/// Apply a base curve from a file written by `rustshift dump-ramps`, in any format.
+	#[arg(long)]
+	pub base_curve: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PreviewArgs {
+	/// How many times faster than real time the schedule is replayed. The default takes a minute.
+	#[arg(long, value_parser = parse_speed, default_value_t = 1440.0)]
+	pub speed: f64,
+	/// How long the replay takes, e.g., `30s` or `2m`, instead of giving `--speed`.
+	#[arg(long, value_parser = parse_duration, conflicts_with = "speed")]
+	pub duration: Option<Duration>,
+}
+
+impl PreviewArgs {
+	pub fn speed(&self) -> f64 {
+		self
+			.duration
+			.map_or(self.speed, |duration| 86_400.0 / duration.as_secs_f64())
+	}
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PlotArgs {
+	/// Write an SVG file instead of drawing in the terminal.
+	#[arg(long)]
+	pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+	/// A local time as `YYYY-MM-DD HH:MM`, optionally with seconds. Defaults to now.
+	#[arg(long, value_parser = parse_datetime)]
+	pub at: Option<PrimitiveDateTime>,
+	#[arg(long, value_enum, default_value_t)]
+	pub format: QueryFormat,
+}
+
+fn parse_speed(input: &str) -> Result<f64, String> {
+	let speed: f64 = input.parse().map_err(|error| format!("{error}"))?;
+	if speed.is_finite() && speed > 0.0 {
+		Ok(speed)
+	} else {
+		Err("must be a positive number".into())
+	}
+}
+
+/// Parses a positive number of seconds, with an optional `s`, `m`, or `h` unit.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+	let input = input.trim();
+	let (number, unit_seconds) = if let Some(number) = input.strip_suffix('h') {
+		(number, 3600.0)
+	} else if let Some(number) = input.strip_suffix('m') {
+		(number, 60.0)
+	} else {
+		(input.strip_suffix('s').unwrap_or(input), 1.0)
+	};
+	let number: f64 = number.trim().parse().map_err(|error| format!("{error}"))?;
+	let seconds = number * unit_seconds;
+	if seconds.is_finite() && seconds > 0.0 {
+		Ok(Duration::from_secs_f64(seconds))
+	} else {
+		Err("must be a positive duration".into())
+	}
+}
+
+/// Parses `YYYY-MM-DD HH:MM` or `YYYY-MM-DD HH:MM:SS`, with a space or a `T` between the date and time.
+fn parse_datetime(input: &str) -> Result<PrimitiveDateTime, String> {
+	let formats = [
+		format_description!("[year]-[month]-[day] [hour]:[minute]"),
+		format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+		format_description!("[year]-[month]-[day]T[hour]:[minute]"),
+		format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"),
+	];
+	let input = input.trim();
+	formats
+		.iter()
+		.find_map(|format| PrimitiveDateTime::parse(input, format).ok())
+		.ok_or_else(|| "expected `YYYY-MM-DD HH:MM`".into())
+}
+
+fn parse_output_path(input: &str) -> Result<(String, PathBuf), String> {
+	let (output, path) = input.split_once('=').ok_or("expected `OUTPUT=PATH`")?;
+	Ok((output.into(), path.into()))
+}
+
+/// Parses a number in the range `0.0..=1.0`.
+fn parse_fraction(input: &str) -> Result<f32, String> {
+	let value: f32 = input.parse().map_err(|error| format!("{error}"))?;
+	if (0.0..=1.0).contains(&value) {
+		Ok(value)
+	} else {
+		Err("must be in the range 0 to 1".into())
+	}
+}
+
+fn parse_non_negative(input: &str) -> Result<f32, String> {
+	let value: f32 = input.parse().map_err(|error| format!("{error}"))?;
+	if value.is_finite() && value >= 0.0 {
+		Ok(value)
+	} else {
+		Err("must be zero or a positive number".into())
+	}
+}
+
+fn parse_positive(input: &str) -> Result<f32, String> {
+	let value: f32 = input.parse().map_err(|error| format!("{error}"))?;
+	if value.is_finite() && value > 0.0 {
+		Ok(value)
+	} else {
+		Err("must be a positive number".into())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::Parser as _;
+
+	use super::Args;
+
+	#[test]
+	fn ambient_lux() {
+		assert!(Args::try_parse_from(["rustshift", "--ambient-dark-lux", "0"]).is_err());
+		assert!(Args::try_parse_from(["rustshift", "--ambient-bright-lux", "NaN"]).is_err());
+		let args = Args::try_parse_from(["rustshift", "--ambient-dark-lux", "600"]).unwrap();
+		assert!(args.validate().is_err());
+		let args = Args::try_parse_from(["rustshift", "--ambient-dark-lux", "50"]).unwrap();
+		assert_eq!(args.validate(), Ok(()));
+	}
+
+	#[test]
+	fn dim_fade() {
+		assert!(Args::try_parse_from(["rustshift", "--dim-fade", "0"]).is_ok());
+		assert!(Args::try_parse_from(["rustshift", "--dim-fade", "-1"]).is_err());
+		assert!(Args::try_parse_from(["rustshift", "--dim-fade", "inf"]).is_err());
+	}
+}
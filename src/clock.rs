@@ -0,0 +1,255 @@
+//! Where the daemon gets the time and time zone from, so that the schedule can be run at simulated times.
+
+use std::cell::{Cell, RefCell};
+use std::time::Instant;
+
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use crate::error::Error;
+
+pub trait Clock {
+	fn now_utc(&self) -> OffsetDateTime;
+
+	/// The local time zone, as a name from the time zone database or a POSIX `TZ` string.
+	fn time_zone(&self) -> Result<String, Error>;
+
+	/// How often the config must be updated, in real time, when minutes pass faster than every real minute.
+	/// Real minutes are handled by the event loop.
+	fn update_interval(&self) -> Option<std::time::Duration> {
+		None
+	}
+
+	/// The local time zone, and the current time in the offset that the schedule follows.
+	///
+	/// Note that the schedule's offset intentionally does not respect daylight savings time in the local timezone.
+	fn schedule_now(&self) -> Result<(TimeZone, OffsetDateTime), Error> {
+		let time_zone = TimeZone::new(&self.time_zone()?)?;
+		let now = time_zone.to_schedule(self.now_utc())?;
+
+		tracing::trace!(time = ?now.time(), "got time");
+
+		Ok((time_zone, now))
+	}
+}
+
+/// A resolved time zone, for converting between UTC and local or schedule time.
+pub struct TimeZone {
+	name: String,
+	time_zone: tz::TimeZone,
+}
+
+impl TimeZone {
+	/// `name` is a name from the time zone database or a POSIX `TZ` string.
+	pub fn new(name: &str) -> Result<Self, Error> {
+		let time_zone = tz::TimeZone::from_posix_tz(name).map_err(|error| {
+			Error::TimeZone(format!(
+				"could not resolve time zone {name:?} to a UTC offset: {error}"
+			))
+		})?;
+		Ok(Self {
+			name: name.to_owned(),
+			time_zone,
+		})
+	}
+
+	fn local_time_type(&self, datetime: OffsetDateTime) -> Result<&tz::LocalTimeType, Error> {
+		self
+			.time_zone
+			.find_local_time_type(datetime.unix_timestamp())
+			.map_err(|error| {
+				Error::TimeZone(format!(
+					"could not find the local time in time zone {:?}: {error}",
+					self.name
+				))
+			})
+	}
+
+	fn utc_offset(&self, seconds: i32) -> Result<UtcOffset, Error> {
+		UtcOffset::from_whole_seconds(seconds).map_err(|error| {
+			Error::TimeZone(format!(
+				"invalid UTC offset in time zone {:?}: {error}",
+				self.name
+			))
+		})
+	}
+
+	/// The offset of the wall clock at `datetime`.
+	pub fn local_offset(&self, datetime: OffsetDateTime) -> Result<UtcOffset, Error> {
+		self.utc_offset(self.local_time_type(datetime)?.ut_offset())
+	}
+
+	/// The offset of the time that the schedule follows at `datetime`,
+	/// which is the wall clock with daylight savings time cancelled out.
+	pub fn schedule_offset(&self, datetime: OffsetDateTime) -> Result<UtcOffset, Error> {
+		let tz_info = self.local_time_type(datetime)?;
+		let mut utc_offset_seconds = tz_info.ut_offset();
+		if !tz_info.is_dst() {
+			utc_offset_seconds += 3600;
+		}
+		self.utc_offset(utc_offset_seconds)
+	}
+
+	/// Converts `datetime` to the wall clock.
+	pub fn to_local(&self, datetime: OffsetDateTime) -> Result<OffsetDateTime, Error> {
+		Ok(datetime.to_offset(self.local_offset(datetime)?))
+	}
+
+	/// Converts `datetime` to the offset that the schedule follows.
+	pub fn to_schedule(&self, datetime: OffsetDateTime) -> Result<OffsetDateTime, Error> {
+		Ok(datetime.to_offset(self.schedule_offset(datetime)?))
+	}
+
+	/// Interprets a wall clock time. Times skipped by a DST change are moved forward by the change,
+	/// and times that occur twice resolve to the first occurrence.
+	pub fn local_to_utc(&self, local: PrimitiveDateTime) -> Result<OffsetDateTime, Error> {
+		// A day before is before any DST change at `local`.
+		let offset_before = self.local_offset(local.assume_utc() - time::Duration::DAY)?;
+		let before = local.assume_offset(offset_before);
+		let offset_after = self.local_offset(before)?;
+		if offset_after == offset_before {
+			return Ok(before);
+		}
+		let after = local.assume_offset(offset_after);
+		if self.local_offset(after)? == offset_after {
+			Ok(after)
+		} else {
+			// In the gap, where neither offset applies.
+			Ok(before)
+		}
+	}
+}
+
+/// The real clock, with the time zone from `TZ` or `/etc/localtime`, for when timedated is not available.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+	fn now_utc(&self) -> OffsetDateTime {
+		OffsetDateTime::now_utc()
+	}
+
+	fn time_zone(&self) -> Result<String, Error> {
+		Ok(std::env::var("TZ").unwrap_or_else(|_| "localtime".into()))
+	}
+}
+
+/// A clock that starts at a given time, and then either stands still or runs at some multiple of real time.
+///
+/// In tests, it can also be advanced and have its time zone changed by hand.
+#[derive(Debug)]
+pub struct SimulatedClock {
+	start: OffsetDateTime,
+	real_start: Instant,
+	/// Simulated seconds per real second. Zero for a fixed time.
+	speed: f64,
+	/// Added with `advance`.
+	offset: Cell<time::Duration>,
+	time_zone: RefCell<String>,
+}
+
+impl SimulatedClock {
+	/// Stands still at `start` until advanced.
+	pub fn new(start: OffsetDateTime, time_zone: &str) -> Self {
+		Self {
+			start,
+			real_start: Instant::now(),
+			speed: 0.0,
+			offset: Cell::new(time::Duration::ZERO),
+			time_zone: RefCell::new(time_zone.to_owned()),
+		}
+	}
+
+	pub fn with_speed(self, speed: f64) -> Self {
+		Self {
+			real_start: Instant::now(),
+			speed,
+			..self
+		}
+	}
+
+	#[cfg(test)]
+	pub fn advance(&self, by: time::Duration) {
+		self.offset.set(self.offset.get() + by);
+	}
+
+	#[cfg(test)]
+	/// Like changing the system time zone. The daemon picks it up on the next update.
+	pub fn set_time_zone(&self, time_zone: &str) {
+		time_zone.clone_into(&mut self.time_zone.borrow_mut());
+	}
+}
+
+impl Clock for SimulatedClock {
+	fn now_utc(&self) -> OffsetDateTime {
+		let elapsed = self.real_start.elapsed().as_secs_f64() * self.speed;
+		self.start + self.offset.get() + time::Duration::seconds_f64(elapsed)
+	}
+
+	fn time_zone(&self) -> Result<String, Error> {
+		Ok(self.time_zone.borrow().clone())
+	}
+
+	fn update_interval(&self) -> Option<std::time::Duration> {
+		(self.speed > 1.0).then(|| std::time::Duration::from_secs_f64(60.0 / self.speed))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::datetime;
+
+	use super::{Clock as _, SimulatedClock};
+
+	/// Central European Time, with summer time from the last Sunday in March to the last Sunday in October.
+	const CET: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+	#[test]
+	fn fixed_time() {
+		let clock = SimulatedClock::new(datetime!(2026-06-01 12:00 UTC), "UTC");
+		assert_eq!(clock.now_utc(), datetime!(2026-06-01 12:00 UTC));
+		clock.advance(time::Duration::minutes(90));
+		assert_eq!(clock.now_utc(), datetime!(2026-06-01 13:30 UTC));
+		assert_eq!(clock.update_interval(), None);
+	}
+
+	#[test]
+	fn accelerated_time() {
+		let start = datetime!(2026-06-01 12:00 UTC);
+		let clock = SimulatedClock::new(start, "UTC").with_speed(60.0);
+		assert!(clock.now_utc() >= start);
+		// A simulated minute takes a real second.
+		assert_eq!(
+			clock.update_interval(),
+			Some(std::time::Duration::from_secs(1))
+		);
+	}
+
+	#[test]
+	fn schedule_time_ignores_dst() {
+		// 2026-03-29 01:00 UTC is when summer time starts, so local time jumps from 02:00 to 04:00.
+		let clock = SimulatedClock::new(datetime!(2026-03-29 00:30 UTC), CET);
+		assert_eq!(
+			clock.schedule_now().unwrap().1.time(),
+			time::macros::time!(02:30)
+		);
+		clock.advance(time::Duration::HOUR);
+		assert_eq!(
+			clock.schedule_now().unwrap().1.time(),
+			time::macros::time!(03:30)
+		);
+	}
+
+	#[test]
+	fn time_zone_change() {
+		let clock = SimulatedClock::new(datetime!(2026-06-01 12:00 UTC), CET);
+		assert_eq!(
+			clock.schedule_now().unwrap().1.time(),
+			time::macros::time!(14:00)
+		);
+		clock.set_time_zone("EST5EDT,M3.2.0,M11.1.0");
+		assert_eq!(
+			clock.schedule_now().unwrap().1.time(),
+			time::macros::time!(08:00)
+		);
+	}
+}
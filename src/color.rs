@@ -1,32 +1,218 @@
 use std::num::NonZeroU32;
 
+pub use self::analytic::WhitePointModel;
 use crate::util::lerp;
 
+mod analytic;
+
 pub type Temperature = NonZeroU32;
 
+/// The lowest supported temperature, in Kelvins.
+/// Temperatures below `TABLE_MIN_TEMPERATURE` are extrapolated such that this temperature is pure red.
+pub const MIN_TEMPERATURE: u32 = 500;
+/// The highest supported temperature, in Kelvins.
+pub const MAX_TEMPERATURE: u32 = 25_000;
+/// The lowest temperature that the white point models are used for directly.
+const TABLE_MIN_TEMPERATURE: u32 = 1000;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-	/// Kelvins. Must be in the range 1000..=25_000 (invariant).
+	/// Kelvins. Must be in the range `MIN_TEMPERATURE..=MAX_TEMPERATURE` (invariant).
 	temperature: Temperature,
 	/// 0.0..=1.0 (invariant) where 0.0 is black and 1.0 is full brightness.
 	brightness: f32,
+	white_point_model: WhitePointModel,
+	/// Used instead of the white point for `temperature` if present.
+	tint: Option<Tint>,
+	filters: Filters,
+}
+
+/// Accessibility filters that are applied together with the white point and brightness.
+///
+/// Gamma ramps map each channel independently, so there is no way to mix channels for true grayscale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Filters {
+	/// Reverse the ramps, so dark content becomes light and vice versa.
+	pub invert: bool,
+	/// Pull every channel's ramp towards the gray (luminance) curve of all three,
+	/// which mutes the colors of the tint while keeping black and the overall brightness.
+	pub reduce_color: bool,
+}
+
+/// How far each channel is pulled towards the gray curve when `Filters::reduce_color` is set.
+const REDUCE_COLOR_STRENGTH: f32 = 0.6;
+
+impl Filters {
+	/// `position` and the returned position are in the range `0.0..=max`.
+	fn apply_to_position(self, position: f32, max: f32) -> f32 {
+		if self.invert {
+			max - position
+		} else {
+			position
+		}
+	}
+
+	/// Applied to the color for each ramp entry, before any calibration curves.
+	fn apply_to_color(self, color: ColorF32) -> ColorF32 {
+		if self.reduce_color {
+			ColorF32::lerp(color, color.gray(), REDUCE_COLOR_STRENGTH)
+		} else {
+			color
+		}
+	}
+}
+
+/// An explicit white point, as an alternative to a temperature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tint {
+	/// Per-channel multipliers, each in the range `0.0..=1.0`.
+	Rgb { red: f32, green: f32, blue: f32 },
+	/// A CIE 1931 chromaticity, which is converted to sRGB and normalized like the analytic white points.
+	Chromaticity { x: f32, y: f32 },
+}
+
+impl Tint {
+	/// No tint at all, i.e., the identity ramps apart from brightness and filters.
+	pub const NEUTRAL: Self = Self::Rgb {
+		red: 1.0,
+		green: 1.0,
+		blue: 1.0,
+	};
+
+	fn white_point(self) -> ColorF32 {
+		match self {
+			Self::Rgb { red, green, blue } => ColorF32 { red, green, blue },
+			Self::Chromaticity { x, y } => analytic::chromaticity_white_point(x, y),
+		}
+	}
+}
+
+/// The syntax of `parse_tint`.
+impl std::fmt::Display for Tint {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Rgb { red, green, blue } => write!(f, "rgb:{red},{green},{blue}"),
+			Self::Chromaticity { x, y } => write!(f, "xy:{x},{y}"),
+		}
+	}
 }
 
 impl Config {
 	pub fn new(temperature: u32, brightness: f32) -> Option<Self> {
-		if (1000..=25000).contains(&temperature) && (0.0..=1.0).contains(&brightness) {
+		if (MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&temperature)
+			&& (0.0..=1.0).contains(&brightness)
+		{
 			Some(Self {
 				temperature: temperature.try_into().ok()?,
 				brightness,
+				white_point_model: WhitePointModel::default(),
+				tint: None,
+				filters: Filters::default(),
 			})
 		} else {
 			None
 		}
 	}
 
+	pub fn temperature(self) -> u32 {
+		self.temperature.get()
+	}
+
+	pub fn brightness(self) -> f32 {
+		self.brightness
+	}
+
+	/// The temperature is clamped to the valid range.
+	#[must_use]
+	pub fn with_temperature(self, temperature: u32) -> Self {
+		Self {
+			temperature: temperature
+				.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE)
+				.try_into()
+				.unwrap(),
+			..self
+		}
+	}
+
+	/// The brightness is clamped to the valid range.
+	#[must_use]
+	pub fn with_brightness(self, brightness: f32) -> Self {
+		Self {
+			brightness: brightness.clamp(0.0, 1.0),
+			..self
+		}
+	}
+
+	#[must_use]
+	pub fn with_white_point_model(self, white_point_model: WhitePointModel) -> Self {
+		Self {
+			white_point_model,
+			..self
+		}
+	}
+
+	#[must_use]
+	pub fn with_tint(self, tint: Option<Tint>) -> Self {
+		Self { tint, ..self }
+	}
+
+	#[must_use]
+	pub fn with_filters(self, filters: Filters) -> Self {
+		Self { filters, ..self }
+	}
+
+	/// Interpolates between two configs.
+	///
+	/// If either config has a tint, the white points are interpolated and the result has an RGB tint.
+	pub fn lerp(from: Self, to: Self, t: f32) -> Self {
+		let tint = (from.tint.is_some() || to.tint.is_some()).then(|| {
+			let ColorF32 { red, green, blue } = ColorF32::lerp(from.white_point(), to.white_point(), t);
+			Tint::Rgb { red, green, blue }
+		});
+		Self {
+			// Clamped in case `t` is outside of `0.0..=1.0`.
+			temperature: (lerp(
+				from.temperature.get() as f32,
+				to.temperature.get() as f32,
+				t,
+			) as u32)
+				.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE)
+				.try_into()
+				.unwrap(),
+			brightness: lerp(from.brightness, to.brightness, t).clamp(0.0, 1.0),
+			white_point_model: if t < 0.5 {
+				from.white_point_model
+			} else {
+				to.white_point_model
+			},
+			tint,
+			filters: if t < 0.5 { from.filters } else { to.filters },
+		}
+	}
+
 	pub fn different_from(self, other: Self) -> bool {
+		let white_point = self.white_point();
+		let other_white_point = other.white_point();
 		self.temperature.get().abs_diff(other.temperature.get()) > 10
 			|| (self.brightness - other.brightness).abs() > 0.01
+			|| self.white_point_model != other.white_point_model
+			|| self.filters != other.filters
+			|| (white_point.red - other_white_point.red).abs() > 0.001
+			|| (white_point.green - other_white_point.green).abs() > 0.001
+			|| (white_point.blue - other_white_point.blue).abs() > 0.001
+	}
+
+	/// The red, green, and blue multipliers of the white point, from the tint if present, or from the temperature.
+	pub fn white_point_channels(self) -> [f32; 3] {
+		let ColorF32 { red, green, blue } = self.white_point();
+		[red, green, blue]
+	}
+
+	fn white_point(self) -> ColorF32 {
+		self.tint.map_or_else(
+			|| white_point(self.white_point_model, self.temperature.get()),
+			Tint::white_point,
+		)
 	}
 }
 
@@ -46,12 +232,15 @@ impl Default for Config {
 		Self {
 			temperature: NEUTRAL_TEMPERATURE,
 			brightness: 1.0,
+			white_point_model: WhitePointModel::default(),
+			tint: None,
+			filters: Filters::default(),
 		}
 	}
 }
 
 pub struct Ramps {
-	/// Invariant: data.len() == num_ramps * 3
+	/// Invariant: `data.len() == num_ramps * 3`
 	/// The data is segmented into three sections: red, green, and blue (SOA).
 	data: Box<[u16]>,
 }
@@ -78,25 +267,95 @@ impl Ramps {
 			.map(|((r, g), b)| [r, g, b])
 	}
 
-	fn ramp_size(&self) -> usize {
+	pub fn ramp_size(&self) -> usize {
 		self.data.len() / 3
 	}
 
+	pub fn channels(&self) -> [&[u16]; 3] {
+		let ramp_size = self.ramp_size();
+		let (red, rest) = self.data.split_at(ramp_size);
+		let (green, blue) = rest.split_at(ramp_size);
+		[red, green, blue]
+	}
+
 	pub fn as_bytes(&self) -> &[u8] {
 		bytemuck::cast_slice(&self.data)
 	}
 }
 
+/// Per-channel curves that the generated ramps are passed through, such as calibration curves.
+#[derive(Debug, Clone)]
+pub struct Curves {
+	/// Each curve is sampled evenly over the input range `0.0..=1.0`,
+	/// and has outputs in the same range.
+	/// Invariant: non-empty.
+	red: Box<[f32]>,
+	green: Box<[f32]>,
+	blue: Box<[f32]>,
+}
+
+impl Curves {
+	/// Returns `None` if any curve is empty.
+	pub fn new(red: Box<[f32]>, green: Box<[f32]>, blue: Box<[f32]>) -> Option<Self> {
+		let curves = [red, green, blue].map(|curve| {
+			curve
+				.iter()
+				.map(|sample| sample.clamp(0.0, 1.0))
+				.collect::<Box<[f32]>>()
+		});
+		if curves.iter().any(|curve| curve.is_empty()) {
+			return None;
+		}
+		let [red, green, blue] = curves;
+		Some(Self { red, green, blue })
+	}
+
+	#[cfg(test)]
+	pub fn channels(&self) -> [&[f32]; 3] {
+		[&self.red, &self.green, &self.blue]
+	}
+
+	fn apply(&self, color: ColorF32) -> ColorF32 {
+		fn sample(curve: &[f32], x: f32) -> f32 {
+			let position = x.clamp(0.0, 1.0) * (curve.len() - 1) as f32;
+			let from_index = position as usize;
+			let to_index = (from_index + 1).min(curve.len() - 1);
+			lerp(
+				curve[from_index],
+				curve[to_index],
+				position - from_index as f32,
+			)
+		}
+
+		ColorF32 {
+			red: sample(&self.red, color.red),
+			green: sample(&self.green, color.green),
+			blue: sample(&self.blue, color.blue),
+		}
+	}
+}
+
 impl Config {
-	pub fn generate_ramps(self, ramps: &mut Ramps) {
-		// We have already checked that `self.temperature` is in the valid range.
-		let white_point = get_white_point(self.temperature.get()).unwrap();
-		let pure_step = 1.0 / ramps.ramp_size() as f32;
+	/// If `curves` is present, the ramps are passed through them after applying the config,
+	/// so that e.g. calibration is preserved.
+	pub fn generate_ramps(self, ramps: &mut Ramps, curves: Option<&Curves>) {
+		let white_point = self.white_point();
+		let ramp_size = ramps.ramp_size();
+		let pure_step = 1.0 / ramp_size as f32;
+		let max_index = ramp_size.saturating_sub(1) as f32;
 		for (i, [r, g, b]) in ramps.iter_rgb_mut().enumerate() {
-			let pure = i as f32 * pure_step * self.brightness;
-			*r = f32_to_u16_full(pure * white_point.red);
-			*g = f32_to_u16_full(pure * white_point.green);
-			*b = f32_to_u16_full(pure * white_point.blue);
+			// Inverting happens first so that e.g. an inverted screen is still dimmed.
+			let index = self.filters.apply_to_position(i as f32, max_index);
+			let pure = index * pure_step * self.brightness;
+			let color = self.filters.apply_to_color(ColorF32 {
+				red: pure * white_point.red,
+				green: pure * white_point.green,
+				blue: pure * white_point.blue,
+			});
+			let color = curves.map_or(color, |curves| curves.apply(color));
+			*r = f32_to_u16_full(color.red);
+			*g = f32_to_u16_full(color.green);
+			*b = f32_to_u16_full(color.blue);
 		}
 	}
 }
@@ -109,6 +368,16 @@ struct ColorF32 {
 }
 
 impl ColorF32 {
+	/// The gray with the same luminance, using the Rec. 709 (sRGB) weights.
+	fn gray(self) -> Self {
+		let luminance = 0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue;
+		Self {
+			red: luminance,
+			green: luminance,
+			blue: luminance,
+		}
+	}
+
 	fn lerp(from: Self, to: Self, t: f32) -> Self {
 		Self {
 			red: lerp(from.red, to.red, t),
@@ -118,6 +387,73 @@ impl ColorF32 {
 	}
 }
 
+/// Parses a temperature given either in Kelvins (`3500`, `3500K`) or in mireds (`285mired`),
+/// returning the temperature in Kelvins.
+pub fn parse_temperature(input: &str) -> Result<u32, String> {
+	let input = input.trim().to_ascii_lowercase();
+	let kelvins = if let Some(mireds) = input
+		.strip_suffix("mireds")
+		.or_else(|| input.strip_suffix("mired"))
+	{
+		let mireds: f32 = mireds
+			.trim()
+			.parse()
+			.map_err(|error| format!("invalid mired value: {error}"))?;
+		if mireds <= 0.0 {
+			return Err("mired value must be positive".into());
+		}
+		(1_000_000.0 / mireds).round() as u32
+	} else {
+		let kelvins = input.strip_suffix('k').unwrap_or(&input);
+		kelvins
+			.trim()
+			.parse()
+			.map_err(|error| format!("invalid temperature: {error}"))?
+	};
+	if (MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&kelvins) {
+		Ok(kelvins)
+	} else {
+		Err(format!(
+			"temperature {kelvins}K is out of range ({MIN_TEMPERATURE}K to {MAX_TEMPERATURE}K)"
+		))
+	}
+}
+
+/// Parses a tint given either as per-channel multipliers (`rgb:1.0,0.75,0.4`)
+/// or as a CIE 1931 chromaticity (`xy:0.48,0.41`).
+pub fn parse_tint(input: &str) -> Result<Tint, String> {
+	fn parse_components<const N: usize>(input: &str) -> Result<[f32; N], String> {
+		let components = input
+			.split(',')
+			.map(|component| component.trim().parse::<f32>())
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|error| format!("invalid tint component: {error}"))?;
+		components
+			.try_into()
+			.map_err(|components: Vec<_>| format!("expected {N} components, got {}", components.len()))
+	}
+
+	let input = input.trim().to_ascii_lowercase();
+	if let Some(rgb) = input.strip_prefix("rgb:") {
+		let [red, green, blue] = parse_components(rgb)?;
+		if ![red, green, blue]
+			.iter()
+			.all(|channel| (0.0..=1.0).contains(channel))
+		{
+			return Err("RGB multipliers must be in the range 0 to 1".into());
+		}
+		Ok(Tint::Rgb { red, green, blue })
+	} else if let Some(xy) = input.strip_prefix("xy:") {
+		let [x, y] = parse_components(xy)?;
+		if !(x > 0.0 && y > 0.0 && x + y < 1.0) {
+			return Err("chromaticity must satisfy x > 0, y > 0, and x + y < 1".into());
+		}
+		Ok(Tint::Chromaticity { x, y })
+	} else {
+		Err("expected a tint starting with `rgb:` or `xy:`".into())
+	}
+}
+
 /// Translates from the f32 range `0.0..=1.0` to the full range of `u16`.
 fn f32_to_u16_full(f: f32) -> u16 {
 	let scaled = f * (f32::from(u16::MAX) + 1.0);
@@ -377,9 +713,30 @@ const BLACK_BODY_COLOR: &[ColorF32] = &[
 	ColorF32 { red: 0.62740336, green: 0.75282962, blue: 1.00000000 }, // 25_100K
 ];
 
+const PURE_RED: ColorF32 = ColorF32 {
+	red: 1.0,
+	green: 0.0,
+	blue: 0.0,
+};
+
+/// `temperature` must be in the range `MIN_TEMPERATURE..=MAX_TEMPERATURE`.
+fn white_point(model: WhitePointModel, temperature: u32) -> ColorF32 {
+	if temperature < TABLE_MIN_TEMPERATURE {
+		// Fade linearly from the lowest modeled white point to pure red at `MIN_TEMPERATURE`.
+		let t = (TABLE_MIN_TEMPERATURE - temperature) as f32
+			/ (TABLE_MIN_TEMPERATURE - MIN_TEMPERATURE) as f32;
+		return ColorF32::lerp(white_point(model, TABLE_MIN_TEMPERATURE), PURE_RED, t);
+	}
+	match model {
+		// We have already checked that `temperature` is in the valid range.
+		WhitePointModel::Table => get_white_point(temperature).unwrap(),
+		model => analytic::white_point(model, temperature as f32),
+	}
+}
+
 /// Returns `None` if the temperature is out of the bounds that we can calculate for.
 fn get_white_point(temperature: u32) -> Option<ColorF32> {
-	let from_index = usize::try_from((temperature - 1000) / 100).unwrap();
+	let from_index = usize::try_from(temperature.checked_sub(TABLE_MIN_TEMPERATURE)? / 100).unwrap();
 	let t = (temperature % 100) as f32 / 100.0;
 	Some(ColorF32::lerp(
 		*BLACK_BODY_COLOR.get(from_index)?,
@@ -387,3 +744,130 @@ fn get_white_point(temperature: u32) -> Option<ColorF32> {
 		t,
 	))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		get_white_point, parse_tint, white_point, ColorF32, Config, Filters, Ramps, Tint,
+		WhitePointModel, MAX_TEMPERATURE, TABLE_MIN_TEMPERATURE,
+	};
+
+	fn max_deviation(model: WhitePointModel, temperatures: impl Iterator<Item = u32>) -> f32 {
+		temperatures
+			.map(|temperature| {
+				let table = get_white_point(temperature).unwrap();
+				let ColorF32 { red, green, blue } = white_point(model, temperature);
+				(red - table.red)
+					.abs()
+					.max((green - table.green).abs())
+					.max((blue - table.blue).abs())
+			})
+			.fold(0.0, f32::max)
+	}
+
+	#[test]
+	fn analytic_models_match_table() {
+		// Each channel stays within 3% of the table, which was also computed from the Planckian locus.
+		for model in [WhitePointModel::Planckian, WhitePointModel::Daylight] {
+			let deviation = max_deviation(
+				model,
+				(TABLE_MIN_TEMPERATURE..=MAX_TEMPERATURE).step_by(100),
+			);
+			assert!(deviation < 0.03, "{model:?} deviates by {deviation}");
+		}
+	}
+
+	#[test]
+	fn parse_tints() {
+		assert_eq!(
+			parse_tint("rgb:1.0, 0.75,0.4"),
+			Ok(Tint::Rgb {
+				red: 1.0,
+				green: 0.75,
+				blue: 0.4
+			})
+		);
+		assert_eq!(
+			parse_tint("XY:0.48,0.41"),
+			Ok(Tint::Chromaticity { x: 0.48, y: 0.41 })
+		);
+		assert!(parse_tint("rgb:1.0,0.75").is_err());
+		assert!(parse_tint("rgb:1.0,1.5,0.4").is_err());
+		assert!(parse_tint("rgb:1.0,-0.1,0.4").is_err());
+		assert!(parse_tint("xy:0.6,0.5").is_err());
+		assert!(parse_tint("xy:0,0.4").is_err());
+		assert!(parse_tint("0.48,0.41").is_err());
+		for tint in ["rgb:1,0.75,0.4", "xy:0.48,0.41"] {
+			assert_eq!(parse_tint(tint).unwrap().to_string(), tint);
+		}
+	}
+
+	#[test]
+	fn lerp_tints() {
+		let warm = Config::default().with_tint(Some(Tint::Rgb {
+			red: 1.0,
+			green: 0.5,
+			blue: 0.0,
+		}));
+		let neutral = Config::default().with_tint(Some(Tint::NEUTRAL));
+		assert_eq!(
+			Config::lerp(warm, neutral, 0.5).tint,
+			Some(Tint::Rgb {
+				red: 1.0,
+				green: 0.75,
+				blue: 0.5
+			})
+		);
+		// A config without a tint contributes its temperature's white point.
+		let cold = Config::default().with_temperature(10_000);
+		let Some(Tint::Rgb { red, green, blue }) = Config::lerp(cold, warm, 0.0).tint else {
+			panic!("expected an RGB tint");
+		};
+		let expected = get_white_point(10_000).unwrap();
+		assert!((red - expected.red).abs() < 1e-6);
+		assert!((green - expected.green).abs() < 1e-6);
+		assert!((blue - expected.blue).abs() < 1e-6);
+		assert_eq!(Config::lerp(cold, Config::default(), 0.5).tint, None);
+	}
+
+	fn ramps(config: Config) -> Ramps {
+		let mut ramps = Ramps::new(256);
+		config.generate_ramps(&mut ramps, None);
+		ramps
+	}
+
+	#[test]
+	fn invert() {
+		let [red, green, blue] = ramps(Config::default().with_filters(Filters {
+			invert: true,
+			reduce_color: false,
+		}))
+		.channels()
+		.map(<[u16]>::to_vec);
+		for channel in [&red, &green, &blue] {
+			assert!(channel[0] > 65_000);
+			assert_eq!(channel[255], 0);
+			assert!(channel.windows(2).all(|pair| pair[0] >= pair[1]));
+		}
+	}
+
+	#[test]
+	fn reduce_color() {
+		let night = Config::new(2500, 1.0).unwrap();
+		let reduced = ramps(night.with_filters(Filters {
+			invert: false,
+			reduce_color: true,
+		}));
+		let unfiltered = ramps(night);
+		let [red, green, blue] = reduced.channels();
+		// Black stays black.
+		assert_eq!([red[0], green[0], blue[0]], [0, 0, 0]);
+		// The channels are closer together, at about the same luminance.
+		let spread = |[red, _green, blue]: [&[u16]; 3]| red[255].abs_diff(blue[255]);
+		assert!(spread(reduced.channels()) < spread(unfiltered.channels()) / 2);
+		let luminance = |[red, green, blue]: [&[u16]; 3]| {
+			0.2126 * f32::from(red[255]) + 0.7152 * f32::from(green[255]) + 0.0722 * f32::from(blue[255])
+		};
+		assert!((luminance(reduced.channels()) - luminance(unfiltered.channels())).abs() < 2.0);
+	}
+}
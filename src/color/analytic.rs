@@ -0,0 +1,134 @@
+//! White points computed from first principles rather than looked up in a table.
+//!
+//! The chromaticity of the light source is found either by integrating Planck's law against the CIE 1931 color matching functions
+//! or, for the daylight model above 4000K, with the CIE daylight locus.
+//! That chromaticity is then converted to linear sRGB, normalized so that the brightest channel is 1, and finally gamma-encoded.
+//! The gamma encoding matches `BLACK_BODY_COLOR`, whose values are multiplied directly into the (gamma-encoded) ramps.
+
+use super::ColorF32;
+
+/// Selects how the white point for a given temperature is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum WhitePointModel {
+	/// Interpolate in the table of white points from gammastep.
+	#[default]
+	Table,
+	/// Compute the white point from the Planckian locus.
+	Planckian,
+	/// Like `Planckian`, but use the CIE daylight locus at 4000K and above.
+	Daylight,
+}
+
+/// Below this temperature the CIE daylight locus is not defined.
+const DAYLIGHT_MIN_TEMPERATURE: f32 = 4000.0;
+
+/// The wavelengths (in nanometers) over which the color matching functions are integrated.
+const WAVELENGTHS: std::ops::RangeInclusive<u16> = 380..=780;
+
+/// Second radiation constant, in meter-kelvins.
+const C2: f64 = 1.438_776_877e-2;
+
+pub fn white_point(model: WhitePointModel, temperature: f32) -> ColorF32 {
+	let (x, y) = match model {
+		WhitePointModel::Daylight if temperature >= DAYLIGHT_MIN_TEMPERATURE => {
+			daylight_locus(temperature)
+		}
+		_ => planckian_locus(temperature),
+	};
+	chromaticity_white_point(x, y)
+}
+
+/// Converts an arbitrary chromaticity to a white point, in the same way as the white point models.
+pub fn chromaticity_white_point(x: f32, y: f32) -> ColorF32 {
+	let linear = xy_to_linear_srgb(x, y).map(|channel| channel.max(0.0));
+	let max = linear[0].max(linear[1]).max(linear[2]);
+	let [red, green, blue] = linear.map(|channel| srgb_encode(channel / max));
+	ColorF32 { red, green, blue }
+}
+
+/// A piecewise Gaussian, as used by `color_matching`.
+fn lobe(wavelength: f64, mean: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+	let sigma = if wavelength < mean {
+		sigma_below
+	} else {
+		sigma_above
+	};
+	let t = (wavelength - mean) / sigma;
+	(-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 2° standard observer color matching functions, using the multi-lobe fit from
+/// Wyman, Sloan, and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+///
+/// `wavelength` is in nanometers. Returns `[x̄, ȳ, z̄]`.
+fn color_matching(wavelength: f64) -> [f64; 3] {
+	let l = wavelength;
+	[
+		1.056 * lobe(l, 599.8, 37.9, 31.0) + 0.362 * lobe(l, 442.0, 16.0, 26.7)
+			- 0.065 * lobe(l, 501.1, 20.4, 26.2),
+		0.821 * lobe(l, 568.8, 46.9, 40.5) + 0.286 * lobe(l, 530.9, 16.3, 31.1),
+		1.217 * lobe(l, 437.0, 11.8, 36.0) + 0.681 * lobe(l, 459.0, 26.0, 13.8),
+	]
+}
+
+/// Spectral radiance of a black body, up to a constant factor.
+///
+/// `wavelength` is in nanometers.
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+	let meters = wavelength * 1e-9;
+	1.0 / (meters.powi(5) * ((C2 / (meters * temperature)).exp() - 1.0))
+}
+
+/// Returns the CIE 1931 xy chromaticity of a black body at the given temperature.
+fn planckian_locus(temperature: f32) -> (f32, f32) {
+	let temperature = f64::from(temperature);
+	let [x, y, z] = WAVELENGTHS
+		.map(f64::from)
+		.map(|wavelength| {
+			let radiance = planck(wavelength, temperature);
+			color_matching(wavelength).map(|weight| weight * radiance)
+		})
+		.fold([0.0; 3], |[ax, ay, az], [x, y, z]| [ax + x, ay + y, az + z]);
+	let sum = x + y + z;
+	((x / sum) as f32, (y / sum) as f32)
+}
+
+/// Returns the CIE 1931 xy chromaticity of the CIE daylight illuminant at the given temperature.
+///
+/// Only defined in the range `4000.0..=25_000.0`.
+#[allow(clippy::unreadable_literal)] // Written as in the standard.
+fn daylight_locus(temperature: f32) -> (f32, f32) {
+	let t = f64::from(temperature);
+	let x = if t <= 7000.0 {
+		-4.6070e9 / t.powi(3) + 2.9678e6 / t.powi(2) + 0.09911e3 / t + 0.244063
+	} else {
+		-2.0064e9 / t.powi(3) + 1.9018e6 / t.powi(2) + 0.24748e3 / t + 0.237040
+	};
+	let y = -3.0 * x * x + 2.870 * x - 0.275;
+	(x as f32, y as f32)
+}
+
+/// Converts an xy chromaticity (with luminance 1) to linear sRGB. The result may be out of gamut.
+#[allow(
+	clippy::unreadable_literal, // Written as in the standard.
+	clippy::excessive_precision, // Consistency.
+)]
+fn xy_to_linear_srgb(x: f32, y: f32) -> [f32; 3] {
+	let big_x = x / y;
+	let big_y = 1.0;
+	let big_z = (1.0 - x - y) / y;
+	[
+		3.2404542 * big_x - 1.5371385 * big_y - 0.4985314 * big_z,
+		-0.9692660 * big_x + 1.8760108 * big_y + 0.0415560 * big_z,
+		0.0556434 * big_x - 0.2040259 * big_y + 1.0572252 * big_z,
+	]
+}
+
+/// The sRGB transfer function, from linear to gamma-encoded.
+fn srgb_encode(linear: f32) -> f32 {
+	if linear <= 0.003_130_8 {
+		linear * 12.92
+	} else {
+		1.055 * linear.powf(1.0 / 2.4) - 0.055
+	}
+}
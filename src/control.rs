@@ -0,0 +1,353 @@
+//! A Unix socket for controlling the running daemon, used by `rustshift ctl`.
+//!
+//! The protocol is line-based: the client sends a single command such as `invert toggle`,
+//! and the daemon replies with `ok` or `error: <message>`.
+//! After `watch`, the daemon keeps the connection open and sends status lines (see `crate::status`).
+
+use std::io::{BufRead, BufReader, ErrorKind, Read as _, Write};
+use std::os::fd::{AsFd as _, BorrowedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::color::{parse_tint, Tint};
+use crate::event_loop::EventSender;
+use crate::Event;
+
+/// How long a client may take to send its command.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+	On,
+	Off,
+	Toggle,
+}
+
+impl Switch {
+	pub fn apply(self, value: &mut bool) {
+		*value = match self {
+			Self::On => true,
+			Self::Off => false,
+			Self::Toggle => !*value,
+		};
+	}
+}
+
+impl FromStr for Switch {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Self, Self::Err> {
+		match input {
+			"on" => Ok(Self::On),
+			"off" => Ok(Self::Off),
+			"toggle" => Ok(Self::Toggle),
+			_ => Err(format!(
+				"unknown switch {input:?}, expected `on`, `off`, or `toggle`"
+			)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+	Dim(Switch),
+	Invert(Switch),
+	ReduceColor(Switch),
+	/// Stop following the schedule, keeping the current temperature.
+	Pause(Switch),
+	/// Use this tint instead of the scheduled white point, or follow the schedule again if `None`.
+	Tint(Option<Tint>),
+}
+
+impl FromStr for Command {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Self, Self::Err> {
+		let mut words = input.split_whitespace();
+		let name = words.next().ok_or("empty command")?;
+		let argument = words.next();
+		if let Some(extra) = words.next() {
+			return Err(format!("unexpected argument {extra:?}"));
+		}
+		// The switch defaults to toggling, which is convenient for key bindings.
+		let switch = || argument.map_or(Ok(Switch::Toggle), str::parse);
+		match name {
+			"dim" => Ok(Self::Dim(switch()?)),
+			"invert" => Ok(Self::Invert(switch()?)),
+			"reduce-color" => Ok(Self::ReduceColor(switch()?)),
+			"pause" => Ok(Self::Pause(switch()?)),
+			"tint" => match argument {
+				None => Err("expected a tint such as `rgb:1.0,0.75,0.4`, or `off`".into()),
+				Some("off") => Ok(Self::Tint(None)),
+				Some(tint) => parse_tint(tint).map(|tint| Self::Tint(Some(tint))),
+			},
+			_ => Err(format!(
+				"unknown command {name:?}, expected `dim`, `invert`, `reduce-color`, `pause`, `tint`, or `watch`"
+			)),
+		}
+	}
+}
+
+fn socket_path() -> Option<PathBuf> {
+	let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+	Some(PathBuf::from(runtime_dir).join("rustshift.sock"))
+}
+
+/// The listening control socket, and the clients that have not sent their command yet.
+///
+/// Everything is non-blocking, and the event loop calls `handle` whenever any of `fds` is readable or `next_deadline` has passed,
+/// so a client that connects and sends nothing cannot stall the daemon.
+/// The socket file is removed when this is dropped.
+pub struct Listener {
+	socket: UnixListener,
+	path: PathBuf,
+	clients: Vec<Client>,
+}
+
+impl Drop for Listener {
+	fn drop(&mut self) {
+		_ = std::fs::remove_file(&self.path);
+	}
+}
+
+struct Client {
+	stream: UnixStream,
+	line: Vec<u8>,
+	/// The client is dropped if it has not sent a full line by then.
+	deadline: Instant,
+}
+
+/// Longer lines are not valid commands, so there is no need to buffer them.
+const MAX_LINE_LENGTH: usize = 1024;
+
+impl Listener {
+	pub fn bind() -> Option<Self> {
+		let Some(path) = socket_path() else {
+			tracing::warn!("XDG_RUNTIME_DIR is not set, not listening for control commands");
+			return None;
+		};
+		Self::bind_at(&path)
+	}
+
+	fn bind_at(path: &Path) -> Option<Self> {
+		if UnixStream::connect(path).is_ok() {
+			tracing::warn!(
+				?path,
+				"another instance is listening on the control socket, not listening for control commands"
+			);
+			return None;
+		}
+		// Remove the socket of a previous instance that did not exit cleanly.
+		_ = std::fs::remove_file(path);
+		let listener = match UnixListener::bind(path).and_then(|listener| {
+			listener.set_nonblocking(true)?;
+			Ok(listener)
+		}) {
+			Ok(listener) => listener,
+			Err(error) => {
+				tracing::warn!(?path, %error, "could not bind control socket");
+				return None;
+			}
+		};
+		Some(Self {
+			socket: listener,
+			path: path.to_owned(),
+			clients: Vec::new(),
+		})
+	}
+
+	/// The listening socket and the pending clients, which are all polled for reading.
+	pub fn fds(&self) -> impl Iterator<Item = BorrowedFd<'_>> {
+		std::iter::once(self.socket.as_fd())
+			.chain(self.clients.iter().map(|client| client.stream.as_fd()))
+	}
+
+	/// When the first pending client times out.
+	pub fn next_deadline(&self) -> Option<Instant> {
+		self.clients.iter().map(|client| client.deadline).min()
+	}
+
+	/// Accepts all pending connections, and handles the commands of all clients that have sent a full line.
+	pub fn handle(&mut self, event_send: &EventSender) {
+		while let Ok((stream, _address)) = self.socket.accept() {
+			if stream.set_nonblocking(true).is_ok() {
+				self.clients.push(Client {
+					stream,
+					line: Vec::new(),
+					deadline: Instant::now() + CLIENT_TIMEOUT,
+				});
+			}
+		}
+		let now = Instant::now();
+		for mut client in std::mem::take(&mut self.clients) {
+			match client.read() {
+				Progress::Line(line) => handle_line(client.stream, &line, event_send),
+				Progress::Pending if client.deadline > now => self.clients.push(client),
+				Progress::Pending => tracing::debug!("dropped control client that did not send a command"),
+				Progress::Failed => {}
+			}
+		}
+	}
+}
+
+enum Progress {
+	Line(String),
+	Pending,
+	/// The client closed the connection without sending anything, sent too much, or the read failed.
+	Failed,
+}
+
+impl Client {
+	/// Reads what is available without blocking.
+	fn read(&mut self) -> Progress {
+		let mut buffer = [0; 256];
+		loop {
+			match (&self.stream).read(&mut buffer) {
+				// The client cannot send any more, so take what it sent as the line.
+				Ok(0) if self.line.is_empty() => return Progress::Failed,
+				Ok(0) => return Progress::Line(String::from_utf8_lossy(&self.line).into_owned()),
+				Ok(length) => {
+					self.line.extend_from_slice(&buffer[..length]);
+					if let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+						return Progress::Line(String::from_utf8_lossy(&self.line[..end]).into_owned());
+					}
+					if self.line.len() > MAX_LINE_LENGTH {
+						return Progress::Failed;
+					}
+				}
+				Err(error) if error.kind() == ErrorKind::Interrupted => {}
+				Err(error) if error.kind() == ErrorKind::WouldBlock => return Progress::Pending,
+				Err(_) => return Progress::Failed,
+			}
+		}
+	}
+}
+
+fn handle_line(mut stream: UnixStream, line: &str, event_send: &EventSender) {
+	if line.trim() == "watch" {
+		// The stream stays non-blocking, so a watcher that stops reading does not block the daemon.
+		if writeln!(stream, "ok").is_ok() {
+			_ = event_send.send(Event::Watch(stream));
+		}
+		return;
+	}
+	let response = match line.parse::<Command>() {
+		Ok(command) => {
+			tracing::debug!(?command, "got control command");
+			_ = event_send.send(Event::Control(command));
+			"ok".into()
+		}
+		Err(error) => format!("error: {error}"),
+	};
+	_ = writeln!(stream, "{response}");
+}
+
+/// Sends a command to the running daemon.
+pub fn send(command: &str) -> Result<(), String> {
+	// Validate locally for better error messages.
+	command.parse::<Command>()?;
+	request(command).map(drop)
+}
+
+/// Sends a line to the running daemon and checks its response.
+/// Returns the connection, from which any further lines can be read.
+pub fn request(line: &str) -> Result<BufReader<UnixStream>, String> {
+	let path = socket_path().ok_or("XDG_RUNTIME_DIR is not set")?;
+	let mut stream = UnixStream::connect(&path)
+		.map_err(|error| format!("could not connect to {}: {error}", path.display()))?;
+	writeln!(stream, "{line}").map_err(|error| format!("could not send command: {error}"))?;
+
+	let mut stream = BufReader::new(stream);
+	let mut response = String::new();
+	stream
+		.read_line(&mut response)
+		.map_err(|error| format!("could not read response: {error}"))?;
+	match response.trim_end().strip_prefix("error: ") {
+		Some(error) => Err(error.into()),
+		None => Ok(stream),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{BufRead as _, BufReader, Write as _};
+	use std::os::unix::net::{UnixListener, UnixStream};
+	use std::time::Instant;
+
+	use super::{Command, Listener, Switch};
+	use crate::color::Tint;
+	use crate::event_loop::EventSender;
+	use crate::Event;
+
+	#[test]
+	fn parse_commands() {
+		assert_eq!("dim".parse(), Ok(Command::Dim(Switch::Toggle)));
+		assert_eq!("pause off".parse(), Ok(Command::Pause(Switch::Off)));
+		assert_eq!(
+			"tint rgb:1,0.5,0.25".parse(),
+			Ok(Command::Tint(Some(Tint::Rgb {
+				red: 1.0,
+				green: 0.5,
+				blue: 0.25
+			})))
+		);
+		assert_eq!("tint off".parse(), Ok(Command::Tint(None)));
+		assert!("tint".parse::<Command>().is_err());
+		assert!("tint rgb:2,0,0".parse::<Command>().is_err());
+		assert!("invert on now".parse::<Command>().is_err());
+	}
+
+	#[test]
+	fn clients_do_not_block() {
+		let directory = tempfile::tempdir().unwrap();
+		let path = directory.path().join("rustshift.sock");
+		let mut listener = Listener::bind_at(&path).unwrap();
+		let (event_send, event_recv) = EventSender::channel();
+
+		// Connects, but sends its command in two parts.
+		let mut slow = UnixStream::connect(&path).unwrap();
+		write!(slow, "dim ").unwrap();
+		let silent = UnixStream::connect(&path).unwrap();
+		listener.handle(&event_send);
+		assert_eq!(listener.fds().count(), 3);
+		assert!(event_recv.try_recv().is_err());
+
+		writeln!(slow, "on").unwrap();
+		listener.handle(&event_send);
+		let mut response = String::new();
+		BufReader::new(&slow).read_line(&mut response).unwrap();
+		assert_eq!(response, "ok\n");
+		assert!(matches!(
+			event_recv.try_recv(),
+			Ok(Event::Control(Command::Dim(Switch::On)))
+		));
+
+		// The silent client is dropped once it timed out.
+		let deadline = listener.next_deadline().unwrap();
+		std::thread::sleep(deadline - Instant::now());
+		listener.handle(&event_send);
+		assert_eq!(listener.fds().count(), 1);
+		assert_eq!(BufReader::new(&silent).read_line(&mut response).unwrap(), 0);
+	}
+
+	#[test]
+	fn does_not_take_over_live_socket() {
+		let directory = tempfile::tempdir().unwrap();
+		let path = directory.path().join("rustshift.sock");
+		let listener = Listener::bind_at(&path).unwrap();
+		assert!(Listener::bind_at(&path).is_none());
+		// The first listener still owns the socket.
+		assert!(UnixStream::connect(&path).is_ok());
+
+		// The socket is removed on exit.
+		drop(listener);
+		assert!(!path.exists());
+
+		// A stale socket, e.g., after a crash, is replaced.
+		drop(UnixListener::bind(&path).unwrap());
+		assert!(path.exists());
+		assert!(Listener::bind_at(&path).is_some());
+	}
+}
@@ -1,15 +1,14 @@
-use std::sync::mpsc::SyncSender;
-
-use time::UtcOffset;
+use futures_lite::stream::{Boxed, StreamExt as _};
 use zbus::{dbus_proxy, fdo};
 
+use crate::clock::Clock;
+use crate::error::Error;
 use crate::Event;
 
 #[dbus_proxy(
 	interface = "org.freedesktop.timedate1",
 	default_service = "org.freedesktop.timedate1",
-	default_path = "/org/freedesktop/timedate1",
-	gen_async = false
+	default_path = "/org/freedesktop/timedate1"
 )]
 trait TimeDate {
 	#[dbus_proxy(property)]
@@ -18,50 +17,55 @@ trait TimeDate {
 
 #[derive(Clone)]
 pub struct DbusTime {
-	proxy: TimeDateProxy<'static>,
+	proxy: TimeDateProxyBlocking<'static>,
 }
 
 impl DbusTime {
-	pub fn connect() -> Self {
-		let dbus = zbus::blocking::Connection::system().expect("connecting to dbus system bus");
-		let proxy = TimeDateProxy::new(&dbus).expect("connecting to dbus timedate protocol");
+	pub fn connect() -> Result<Self, Error> {
+		let dbus = zbus::blocking::Connection::system()
+			.map_err(Error::dbus("could not connect to the D-Bus system bus"))?;
+		let proxy =
+			TimeDateProxyBlocking::new(&dbus).map_err(Error::dbus("could not connect to timedated"))?;
 
-		Self { proxy }
+		Ok(Self { proxy })
 	}
 
-	pub fn handle_timezone_updates(&self, event_send: &SyncSender<Event>) {
-		let mut changes = self.proxy.receive_property_changed::<String>("Timezone");
-		// Ignore the first change, which isn't really a change at all.
-		_ = changes.next();
-		for change in changes {
-			tracing::trace!(new_timezone = change.get().unwrap(), "got timezone update");
-			if event_send.send(Event::Update).is_err() {
-				break;
-			}
-		}
+	/// The connection to the system bus, which can be shared by everything else that needs it.
+	pub fn connection(&self) -> &zbus::blocking::Connection {
+		self.proxy.inner().connection()
 	}
 
-	/// Note that the returned time intentionally does not respect daylight savings time in the local timezone.
-	pub fn get_time(&self) -> time::Time {
-		let time_zone_name = self.proxy.timezone().unwrap();
-		let time_zone = tz::TimeZone::from_posix_tz(&time_zone_name).unwrap_or_else(|error| {
-			panic!("error resolving time zone name {time_zone_name:?} to a UTC offset: {error}")
-		});
-		let datetime_utc = time::OffsetDateTime::now_utc();
-		let tz_info = time_zone
-			.find_local_time_type(datetime_utc.unix_timestamp())
-			.unwrap();
-		let mut utc_offset_seconds = tz_info.ut_offset();
-		// Cancel out daylight savings time.
-		if !tz_info.is_dst() {
-			utc_offset_seconds += 3600;
-		}
-		let utc_offset = UtcOffset::from_whole_seconds(utc_offset_seconds).unwrap();
-		let datetime_local = datetime_utc.to_offset(utc_offset);
-		let time = datetime_local.time();
+	/// `Event::Update` whenever the system time zone changes.
+	pub async fn timezone_events(dbus: &zbus::Connection) -> Result<Boxed<Event>, Error> {
+		let proxy = TimeDateProxy::new(dbus)
+			.await
+			.map_err(Error::dbus("could not connect to timedated"))?;
+		Ok(
+			proxy
+				.receive_timezone_changed()
+				.await
+				// Ignore the first change, which isn't really a change at all.
+				.skip(1)
+				.then(|change| async move { change.get().await.ok() })
+				.map(|new_timezone| {
+					tracing::trace!(new_timezone, "got timezone update");
+					Event::Update
+				})
+				.boxed(),
+		)
+	}
+}
 
-		tracing::trace!(?time, "got time");
+/// The real clock, with the system time zone from timedated.
+impl Clock for DbusTime {
+	fn now_utc(&self) -> time::OffsetDateTime {
+		time::OffsetDateTime::now_utc()
+	}
 
-		time
+	fn time_zone(&self) -> Result<String, Error> {
+		self
+			.proxy
+			.timezone()
+			.map_err(|error| Error::dbus("could not get the time zone")(error.into()))
 	}
 }
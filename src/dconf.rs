@@ -0,0 +1,305 @@
+//! Reading and writing the user's dconf database, which is where `GSettings` are stored.
+//!
+//! Like `GSettings` itself, values are read directly from the database file, and changed through the dconf service on the session bus.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use byteorder::NativeEndian;
+use zbus::dbus_proxy;
+use zbus::zvariant::{self, EncodingContext};
+
+use crate::error::Error;
+
+#[dbus_proxy(
+	interface = "ca.desrt.dconf.Writer",
+	default_service = "ca.desrt.dconf",
+	default_path = "/ca/desrt/dconf/Writer/user",
+	gen_async = false
+)]
+trait Writer {
+	/// `changes` is a serialized `a{smv}` from keys to their new values, or to nothing to reset them.
+	fn change(&self, changes: &[u8]) -> zbus::Result<String>;
+}
+
+/// The size of a `struct gvdb_hash_item`.
+const GVDB_ITEM_SIZE: usize = 24;
+
+/// A value of one of the types that we need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+	Bool(bool),
+	U32(u32),
+	F64(f64),
+}
+
+/// The syntax of `gsettings`, e.g., `true`, `uint32 2700`, or `20.0`.
+impl Display for Value {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Bool(value) => write!(f, "{value}"),
+			Self::U32(value) => write!(f, "uint32 {value}"),
+			Self::F64(value) => write!(f, "{value:?}"),
+		}
+	}
+}
+
+impl FromStr for Value {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Self, Self::Err> {
+		let error = || format!("invalid value {input:?}");
+		match input {
+			"true" => Ok(Self::Bool(true)),
+			"false" => Ok(Self::Bool(false)),
+			_ => match input.strip_prefix("uint32 ") {
+				Some(value) => value.parse().map(Self::U32).map_err(|_| error()),
+				None => input.parse().map(Self::F64).map_err(|_| error()),
+			},
+		}
+	}
+}
+
+impl From<Value> for zvariant::Value<'static> {
+	fn from(value: Value) -> Self {
+		match value {
+			Value::Bool(value) => value.into(),
+			Value::U32(value) => value.into(),
+			Value::F64(value) => value.into(),
+		}
+	}
+}
+
+pub struct Dconf {
+	writer: WriterProxy<'static>,
+	database: PathBuf,
+}
+
+impl Dconf {
+	pub fn new(dbus: &zbus::blocking::Connection) -> Result<Self, Error> {
+		let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+			.map(PathBuf::from)
+			.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+			.ok_or_else(|| {
+				Error::io("could not find the dconf database")(std::io::Error::other(
+					"neither XDG_CONFIG_HOME nor HOME is set",
+				))
+			})?;
+		Self::with_database(dbus, config_dir.join("dconf/user"))
+	}
+
+	pub fn with_database(
+		dbus: &zbus::blocking::Connection,
+		database: PathBuf,
+	) -> Result<Self, Error> {
+		let writer =
+			WriterProxy::new(dbus).map_err(Error::dbus("could not connect to the dconf service"))?;
+		Ok(Self { writer, database })
+	}
+
+	/// Returns the values of `keys`, with `None` for keys that are not set, i.e., that have their default value.
+	pub fn read(&self, keys: impl IntoIterator<Item = String>) -> Result<Vec<Option<Value>>, Error> {
+		let values = match std::fs::read(&self.database) {
+			Ok(data) => parse_database(&data).map_err(|error| {
+				Error::io("could not read the dconf database")(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					error,
+				))
+			})?,
+			// Nothing has been set yet.
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+			Err(error) => return Err(Error::io("could not read the dconf database")(error)),
+		};
+		Ok(
+			keys
+				.into_iter()
+				.map(|key| values.get(&key).copied())
+				.collect(),
+		)
+	}
+
+	/// Sets the keys to the values, or resets them to their default value if `None`, all at once.
+	pub fn write(
+		&self,
+		changes: impl IntoIterator<Item = (String, Option<Value>)>,
+	) -> Result<(), Error> {
+		let changes = changes
+			.into_iter()
+			.map(|(key, value)| (key, value.map(zvariant::Value::from)))
+			.collect::<HashMap<_, _>>();
+		let changes = zvariant::to_bytes(EncodingContext::<NativeEndian>::new_gvariant(0), &changes)
+			.map_err(|error| Error::dbus("could not serialize the dconf changes")(error.into()))?;
+		self
+			.writer
+			.change(&changes)
+			.map(drop)
+			.map_err(Error::dbus("could not change the dconf database"))
+	}
+}
+
+/// Parses the keys and values from a GVDB file, as written by the dconf service.
+/// Values of types other than `b`, `u`, and `d` are skipped, since nothing else is needed.
+fn parse_database(data: &[u8]) -> Result<HashMap<String, Value>, String> {
+	let read_u32 = |position: usize| {
+		data
+			.get(position..position + 4)
+			.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+			.ok_or_else(|| "unexpected end of database".to_owned())
+	};
+	if data.get(0..8) != Some(b"GVariant") {
+		return Err("not a GVDB file".into());
+	}
+	// The root hash table: a header, the bloom filter, the buckets, and then the items.
+	let (start, end) = (read_u32(16)? as usize, read_u32(20)? as usize);
+	let bloom_words = (read_u32(start)? & ((1 << 27) - 1)) as usize;
+	let buckets = read_u32(start + 4)? as usize;
+	let items_start = start + 8 + 4 * (bloom_words + buckets);
+	if end > data.len() || items_start > end {
+		return Err("the hash table is out of bounds".into());
+	}
+
+	// The parent, name, type, and value of each item.
+	let items = (0..(end - items_start) / GVDB_ITEM_SIZE)
+		.map(|index| {
+			let item = items_start + index * GVDB_ITEM_SIZE;
+			let key_start = read_u32(item + 8)? as usize;
+			let key_size = usize::from(u16::from_le_bytes([data[item + 12], data[item + 13]]));
+			let key = data
+				.get(key_start..key_start + key_size)
+				.ok_or("a key is out of bounds")?;
+			let value = read_u32(item + 16)? as usize..read_u32(item + 20)? as usize;
+			Ok((read_u32(item + 4)?, key, data[item + 14], value))
+		})
+		.collect::<Result<Vec<_>, String>>()?;
+	// Keys are stored relative to their parent, e.g., `night-light-enabled` in `color/`.
+	let full_key = |mut index: usize| {
+		let mut parts = Vec::new();
+		// An item cannot have more ancestors than there are items.
+		for _ in 0..=items.len() {
+			let (parent, key, ..) = items.get(index).ok_or("an item has an invalid parent")?;
+			parts.push(*key);
+			if *parent == u32::MAX {
+				parts.reverse();
+				return Ok(String::from_utf8_lossy(&parts.concat()).into_owned());
+			}
+			index = *parent as usize;
+		}
+		Err("the items' parents form a cycle".to_owned())
+	};
+
+	let mut values = HashMap::new();
+	for (index, (_parent, _key, kind, value)) in items.iter().enumerate() {
+		if *kind != b'v' {
+			continue;
+		}
+		let value = data.get(value.clone()).ok_or("a value is out of bounds")?;
+		if let Some(value) = parse_variant(value) {
+			values.insert(full_key(index)?, value);
+		}
+	}
+	Ok(values)
+}
+
+/// Parses a serialized `GVariant` of type `v`, which is the value followed by a null byte and its type.
+fn parse_variant(data: &[u8]) -> Option<Value> {
+	let separator = data.iter().rposition(|&byte| byte == 0)?;
+	match (&data[separator + 1..], &data[..separator]) {
+		(b"b", &[value]) => Some(Value::Bool(value != 0)),
+		(b"u", value) => Some(Value::U32(u32::from_ne_bytes(value.try_into().ok()?))),
+		(b"d", value) => Some(Value::F64(f64::from_ne_bytes(value.try_into().ok()?))),
+		_ => None,
+	}
+}
+
+/// Writes a GVDB file with the values, each of which is stored with its full key and no parent.
+#[cfg(test)]
+pub fn database(values: &[(&str, Value)]) -> Vec<u8> {
+	let mut data = b"GVariant".to_vec();
+	let table_start = 24;
+	let table_end = table_start + 8 + values.len() * GVDB_ITEM_SIZE;
+	for value in [0, 0, table_start, table_end] {
+		data.extend_from_slice(&(value as u32).to_le_bytes());
+	}
+	// No bloom filter and no buckets.
+	data.extend_from_slice(&[0; 8]);
+
+	let mut heap = Vec::new();
+	for (key, value) in values {
+		let key_start = table_end + heap.len();
+		heap.extend_from_slice(key.as_bytes());
+		let value_start = table_end + heap.len();
+		let (bytes, kind): (Vec<u8>, &[u8]) = match *value {
+			Value::Bool(value) => (vec![value.into()], b"b"),
+			Value::U32(value) => (value.to_ne_bytes().into(), b"u"),
+			Value::F64(value) => (value.to_ne_bytes().into(), b"d"),
+		};
+		heap.extend_from_slice(&bytes);
+		heap.push(0);
+		heap.extend_from_slice(kind);
+		let value_end = table_end + heap.len();
+
+		data.extend_from_slice(&0u32.to_le_bytes());
+		data.extend_from_slice(&u32::MAX.to_le_bytes());
+		data.extend_from_slice(&(key_start as u32).to_le_bytes());
+		data.extend_from_slice(&(key.len() as u16).to_le_bytes());
+		data.extend_from_slice(b"v\0");
+		data.extend_from_slice(&(value_start as u32).to_le_bytes());
+		data.extend_from_slice(&(value_end as u32).to_le_bytes());
+	}
+	data.extend_from_slice(&heap);
+	data
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{database, parse_database, Value};
+
+	#[test]
+	fn parse_values() {
+		assert_eq!("true".parse(), Ok(Value::Bool(true)));
+		assert_eq!("uint32 2700".parse(), Ok(Value::U32(2700)));
+		assert_eq!("20.5".parse(), Ok(Value::F64(20.5)));
+		assert!("'text'".parse::<Value>().is_err());
+		for value in [Value::Bool(false), Value::U32(4000), Value::F64(6.0)] {
+			assert_eq!(value.to_string().parse(), Ok(value));
+		}
+	}
+
+	#[test]
+	fn read_database() {
+		let data = database(&[
+			("/a/enabled", Value::Bool(false)),
+			("/a/temperature", Value::U32(2700)),
+			("/a/from", Value::F64(20.5)),
+		]);
+		let values = parse_database(&data).unwrap();
+		assert_eq!(values.len(), 3);
+		assert_eq!(values["/a/enabled"], Value::Bool(false));
+		assert_eq!(values["/a/temperature"], Value::U32(2700));
+		assert_eq!(values["/a/from"], Value::F64(20.5));
+
+		assert!(parse_database(&data[..40]).is_err());
+		assert!(parse_database(b"not a database").is_err());
+	}
+
+	#[test]
+	fn keys_relative_to_parents() {
+		let mut data = database(&[
+			("/", Value::Bool(true)),
+			("a/", Value::Bool(true)),
+			("b", Value::U32(1)),
+		]);
+		// Make the items a chain of `/`, `a/`, and `b`.
+		let items = 32;
+		data[items + 24 + 4..][..4].copy_from_slice(&0u32.to_le_bytes());
+		data[items + 48 + 4..][..4].copy_from_slice(&1u32.to_le_bytes());
+		let values = parse_database(&data).unwrap();
+		assert_eq!(values["/a/b"], Value::U32(1));
+
+		// A cycle.
+		data[items + 4..][..4].copy_from_slice(&2u32.to_le_bytes());
+		assert!(parse_database(&data).is_err());
+	}
+}
@@ -0,0 +1,521 @@
+//! The `drm` gamma backend, which sets the gamma of the CRTCs of `/dev/dri/card*` directly.
+//!
+//! This only works if no display server is running, since otherwise it is the DRM master.
+//! Hotplugging is detected through kernel uevents.
+
+use std::fs::File;
+use std::os::fd::{AsFd, AsRawFd as _, BorrowedFd, OwnedFd};
+use std::path::PathBuf;
+
+use drm::control::{connector, crtc, property, Device as _};
+use nix::sys::socket::{
+	bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+
+use crate::backend::{GammaBackend, OutputId, OutputInfo};
+use crate::color::Ramps;
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::Event;
+
+/// `GAMMA_LUT` can be much larger than the legacy gamma, e.g. 4096 entries or more.
+/// Ramps of up to this size are generated, and resampled to the LUT size.
+const MAX_RAMP_SIZE: usize = 1024;
+
+struct Card {
+	file: File,
+	path: PathBuf,
+}
+
+impl AsFd for Card {
+	fn as_fd(&self) -> BorrowedFd<'_> {
+		self.file.as_fd()
+	}
+}
+
+impl drm::Device for Card {}
+impl drm::control::Device for Card {}
+
+impl Card {
+	/// Returns the value of the object's property with the given name, along with the property.
+	fn find_property(
+		&self,
+		handle: crtc::Handle,
+		name: &str,
+	) -> std::io::Result<Option<(property::Handle, property::RawValue)>> {
+		for (&property, &value) in self.get_properties(handle)?.iter() {
+			if self.get_property(property)?.name().to_bytes() == name.as_bytes() {
+				return Ok(Some((property, value)));
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Reading and setting `GAMMA_LUT` blobs, separate from `Card` so that saving and restoring the LUT can be tested.
+trait LutBlobs {
+	fn read_blob(&self, blob: property::RawValue) -> std::io::Result<Vec<u8>>;
+
+	/// Sets the property to a new blob with these contents, or to no blob.
+	fn set_blob(
+		&self,
+		handle: crtc::Handle,
+		property: property::Handle,
+		data: Option<&[u8]>,
+	) -> std::io::Result<()>;
+}
+
+impl LutBlobs for Card {
+	fn read_blob(&self, blob: property::RawValue) -> std::io::Result<Vec<u8>> {
+		self.get_property_blob(blob)
+	}
+
+	fn set_blob(
+		&self,
+		handle: crtc::Handle,
+		property: property::Handle,
+		data: Option<&[u8]>,
+	) -> std::io::Result<()> {
+		let Some(data) = data else {
+			return self.set_property(handle, property, 0);
+		};
+		let blob = drm_ffi::mode::create_property_blob(self.as_fd(), &mut data.to_vec())?;
+		let result = self.set_property(handle, property, blob.blob_id.into());
+		// The CRTC keeps its own reference to the blob.
+		_ = self.destroy_property_blob(blob.blob_id.into());
+		result
+	}
+}
+
+/// Copies the LUT, since the blob itself is freed once the CRTC stops using it.
+fn save_lut(card: &impl LutBlobs, blob: property::RawValue) -> std::io::Result<Option<Vec<u8>>> {
+	if blob == 0 {
+		return Ok(None);
+	}
+	card.read_blob(blob).map(Some)
+}
+
+/// How the gamma of a CRTC is set.
+enum Gamma {
+	Lut {
+		property: property::Handle,
+		size: usize,
+		/// A copy of the LUT from before we changed it, which is restored on exit.
+		/// `None` if there was no LUT, which means that the gamma is linear.
+		original: Option<Vec<u8>>,
+	},
+	Legacy {
+		/// The ramps from before we changed them, which are restored on exit.
+		original: [Vec<u16>; 3],
+	},
+}
+
+struct Crtc {
+	id: OutputId,
+	card: usize,
+	handle: crtc::Handle,
+	/// The name of the connector driven by the CRTC, e.g., `DP-1`.
+	name: Box<str>,
+	gamma: Gamma,
+}
+
+pub struct Backend {
+	cards: Vec<Card>,
+	/// Receives kernel uevents, if possible.
+	uevents: Option<OwnedFd>,
+	event_send: EventSender,
+	crtcs: Vec<Crtc>,
+	next_id: OutputId,
+}
+
+impl Backend {
+	pub fn new(event_send: EventSender) -> Result<Self, Error> {
+		let mut paths = std::fs::read_dir("/dev/dri")
+			.map_err(Error::io("could not list DRM devices"))?
+			.filter_map(|entry| Some(entry.ok()?.path()))
+			.filter(|path| {
+				path
+					.file_name()
+					.is_some_and(|name| name.to_string_lossy().starts_with("card"))
+			})
+			.collect::<Vec<_>>();
+		paths.sort();
+		let cards = paths
+			.into_iter()
+			.filter_map(|path| {
+				let file = File::options()
+					.read(true)
+					.write(true)
+					.open(&path)
+					.inspect_err(|error| tracing::warn!(?path, %error, "could not open DRM device"))
+					.ok()?;
+				Some(Card { file, path })
+			})
+			.collect::<Vec<_>>();
+		if cards.is_empty() {
+			return Err(Error::io("could not open any DRM device")(
+				std::io::ErrorKind::NotFound.into(),
+			));
+		}
+
+		let uevents = open_uevent_socket()
+			.inspect_err(
+				|error| tracing::warn!(%error, "could not listen for uevents, not detecting hotplugging"),
+			)
+			.ok();
+
+		let mut backend = Self {
+			cards,
+			uevents,
+			event_send,
+			crtcs: Vec::new(),
+			next_id: 0,
+		};
+		backend.update_crtcs();
+		_ = backend.event_send.send(Event::OutputsEnumerated);
+		Ok(backend)
+	}
+
+	/// Enumerates the CRTCs that drive connected connectors,
+	/// and sends `Event::RemoveOutput` and `Event::AddOutput` for the ones that changed.
+	/// Errors are only logged, since the other cards may still work.
+	fn update_crtcs(&mut self) {
+		let mut old_crtcs = std::mem::take(&mut self.crtcs);
+		for (card_index, card) in self.cards.iter().enumerate() {
+			let found = match enabled_crtcs(card) {
+				Ok(found) => found,
+				Err(error) => {
+					tracing::warn!(path = ?card.path, %error, "could not enumerate CRTCs");
+					continue;
+				}
+			};
+			for (handle, name) in found {
+				if let Some(index) = old_crtcs
+					.iter()
+					.position(|crtc| crtc.card == card_index && crtc.handle == handle && *crtc.name == *name)
+				{
+					self.crtcs.push(old_crtcs.swap_remove(index));
+					continue;
+				}
+
+				let gamma = match gamma(card, handle) {
+					Ok(Some(gamma)) => gamma,
+					Ok(None) => {
+						tracing::debug!(?name, "CRTC does not support gamma");
+						continue;
+					}
+					Err(error) => {
+						tracing::warn!(?name, %error, "could not get the gamma of CRTC");
+						continue;
+					}
+				};
+				let ramp_size = match &gamma {
+					Gamma::Lut { size, .. } => (*size).min(MAX_RAMP_SIZE),
+					Gamma::Legacy { original } => original[0].len(),
+				};
+				let id = self.next_id;
+				self.next_id += 1;
+				_ = self.event_send.send(Event::AddOutput(OutputInfo {
+					id,
+					name: Some(name.clone()),
+					description: name.clone(),
+					ramp_size,
+				}));
+				self.crtcs.push(Crtc {
+					id,
+					card: card_index,
+					handle,
+					name,
+					gamma,
+				});
+			}
+		}
+
+		for crtc in old_crtcs {
+			_ = self.event_send.send(Event::RemoveOutput { id: crtc.id });
+		}
+	}
+}
+
+/// Returns the CRTCs that drive connected connectors, along with the connectors' names.
+fn enabled_crtcs(card: &Card) -> std::io::Result<Vec<(crtc::Handle, Box<str>)>> {
+	let resources = card.resource_handles()?;
+	let mut crtcs = Vec::new();
+	for &connector in resources.connectors() {
+		let connector = card.get_connector(connector, false)?;
+		if connector.state() != connector::State::Connected {
+			continue;
+		}
+		let Some(encoder) = connector.current_encoder() else {
+			continue;
+		};
+		let Some(crtc) = card.get_encoder(encoder)?.crtc() else {
+			continue;
+		};
+		let name = format!(
+			"{}-{}",
+			connector.interface().as_str(),
+			connector.interface_id()
+		);
+		crtcs.push((crtc, name.into()));
+	}
+	Ok(crtcs)
+}
+
+/// Prefers `GAMMA_LUT`, and falls back to the legacy gamma.
+fn gamma(card: &Card, handle: crtc::Handle) -> std::io::Result<Option<Gamma>> {
+	let lut = card.find_property(handle, "GAMMA_LUT")?;
+	let lut_size = card.find_property(handle, "GAMMA_LUT_SIZE")?;
+	if let (Some((property, blob)), Some((_, size))) = (lut, lut_size) {
+		if size > 0 {
+			return Ok(Some(Gamma::Lut {
+				property,
+				size: size as usize,
+				original: save_lut(card, blob)?,
+			}));
+		}
+	}
+
+	let size = card.get_crtc(handle)?.gamma_length() as usize;
+	if size == 0 {
+		return Ok(None);
+	}
+	let mut original = [vec![0; size], vec![0; size], vec![0; size]];
+	let [red, green, blue] = &mut original;
+	card.get_gamma(handle, red, green, blue)?;
+	Ok(Some(Gamma::Legacy { original }))
+}
+
+/// Linearly interpolates `channel` to `size` entries.
+fn resample(channel: &[u16], size: usize) -> impl Iterator<Item = u16> + '_ {
+	let scale = (channel.len() - 1) as f32 / (size - 1).max(1) as f32;
+	(0..size).map(move |index| {
+		let position = index as f32 * scale;
+		let before = position.floor() as usize;
+		let after = (before + 1).min(channel.len() - 1);
+		let t = position - before as f32;
+		crate::util::lerp(f32::from(channel[before]), f32::from(channel[after]), t).round() as u16
+	})
+}
+
+fn open_uevent_socket() -> nix::Result<OwnedFd> {
+	let socket = socket(
+		AddressFamily::Netlink,
+		SockType::Datagram,
+		SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+		SockProtocol::NetlinkKObjectUEvent,
+	)?;
+	// Group 1 has the uevents from the kernel.
+	bind(socket.as_raw_fd(), &NetlinkAddr::new(0, 1))?;
+	Ok(socket)
+}
+
+/// Whether the uevent is a DRM hotplug, i.e., connectors may have been connected or disconnected.
+fn is_drm_hotplug(uevent: &[u8]) -> bool {
+	// The uevent is a list of null-terminated `KEY=VALUE` pairs, after a header.
+	let mut fields = uevent.split(|&byte| byte == 0);
+	fields.clone().any(|field| field == b"SUBSYSTEM=drm") && fields.any(|field| field == b"HOTPLUG=1")
+}
+
+impl GammaBackend for Backend {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		let Some(uevents) = &self.uevents else {
+			return Ok(());
+		};
+		let mut hotplug = false;
+		let mut buffer = [0; 4096];
+		while let Ok(len) = recv(uevents.as_raw_fd(), &mut buffer, MsgFlags::empty()) {
+			hotplug |= is_drm_hotplug(&buffer[..len]);
+		}
+		if hotplug {
+			self.update_crtcs();
+		}
+		Ok(())
+	}
+
+	fn fd(&self) -> Option<BorrowedFd<'_>> {
+		self.uevents.as_ref().map(AsFd::as_fd)
+	}
+
+	fn set_gamma(&mut self, output: OutputId, ramps: &Ramps) -> Result<(), Error> {
+		let Some(crtc) = self.crtcs.iter().find(|crtc| crtc.id == output) else {
+			return Ok(());
+		};
+		let card = &self.cards[crtc.card];
+		let [red, green, blue] = ramps.channels();
+		match &crtc.gamma {
+			Gamma::Lut { property, size, .. } => {
+				// `struct drm_color_lut`
+				let lut = resample(red, *size)
+					.zip(resample(green, *size))
+					.zip(resample(blue, *size))
+					.map(|((red, green), blue)| [red, green, blue, 0])
+					.collect::<Vec<[u16; 4]>>();
+				card
+					.set_blob(crtc.handle, *property, Some(bytemuck::cast_slice(&lut)))
+					.map_err(Error::io("could not set the gamma LUT"))?;
+			}
+			Gamma::Legacy { .. } => {
+				card
+					.set_gamma(crtc.handle, red, green, blue)
+					.map_err(Error::io("could not set the gamma"))?;
+			}
+		}
+		Ok(())
+	}
+
+	/// The ioctls are synchronous.
+	fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+/// Like with X11, the gamma stays after we exit, so it has to be restored.
+impl Drop for Backend {
+	fn drop(&mut self) {
+		for crtc in &self.crtcs {
+			let card = &self.cards[crtc.card];
+			let result = match &crtc.gamma {
+				Gamma::Lut {
+					property, original, ..
+				} => card.set_blob(crtc.handle, *property, original.as_deref()),
+				Gamma::Legacy {
+					original: [red, green, blue],
+				} => card.set_gamma(crtc.handle, red, green, blue),
+			};
+			if let Err(error) = result {
+				tracing::warn!(name = ?crtc.name, %error, "could not restore the gamma");
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+
+	use drm::control::{crtc, property};
+
+	use super::{is_drm_hotplug, resample, save_lut, LutBlobs};
+
+	/// A CRTC with a `GAMMA_LUT` property, whose blobs are freed as soon as they are replaced, like the kernel does.
+	struct FakeCard {
+		blobs: RefCell<Vec<Option<Vec<u8>>>>,
+		lut: RefCell<property::RawValue>,
+	}
+
+	impl FakeCard {
+		fn new(lut: Option<&[u8]>) -> Self {
+			let card = Self {
+				blobs: RefCell::new(Vec::new()),
+				lut: RefCell::new(0),
+			};
+			card.set_blob(handle(), property(), lut).unwrap();
+			card
+		}
+
+		fn lut(&self) -> Option<Vec<u8>> {
+			let blob = *self.lut.borrow();
+			(blob != 0).then(|| self.read_blob(blob).unwrap())
+		}
+	}
+
+	impl LutBlobs for FakeCard {
+		fn read_blob(&self, blob: property::RawValue) -> std::io::Result<Vec<u8>> {
+			self.blobs.borrow()[blob as usize - 1]
+				.clone()
+				.ok_or_else(|| std::io::ErrorKind::NotFound.into())
+		}
+
+		fn set_blob(
+			&self,
+			_handle: crtc::Handle,
+			_property: property::Handle,
+			data: Option<&[u8]>,
+		) -> std::io::Result<()> {
+			let mut blobs = self.blobs.borrow_mut();
+			let old = std::mem::take(&mut *self.lut.borrow_mut());
+			if old != 0 {
+				blobs[old as usize - 1] = None;
+			}
+			if let Some(data) = data {
+				blobs.push(Some(data.to_vec()));
+				*self.lut.borrow_mut() = blobs.len() as property::RawValue;
+			}
+			Ok(())
+		}
+	}
+
+	fn handle() -> crtc::Handle {
+		drm::control::from_u32(1).unwrap()
+	}
+
+	fn property() -> property::Handle {
+		drm::control::from_u32(2).unwrap()
+	}
+
+	#[test]
+	fn restores_the_original_lut() {
+		let card = FakeCard::new(Some(&[1, 2, 3, 4]));
+		let original = save_lut(&card, *card.lut.borrow()).unwrap();
+		card
+			.set_blob(handle(), property(), Some(&[5, 6, 7, 8]))
+			.unwrap();
+		// The original blob is gone by now.
+		assert!(card.read_blob(1).is_err());
+
+		card
+			.set_blob(handle(), property(), original.as_deref())
+			.unwrap();
+		assert_eq!(card.lut().as_deref(), Some(&[1, 2, 3, 4][..]));
+	}
+
+	#[test]
+	fn restores_no_lut() {
+		let card = FakeCard::new(None);
+		let original = save_lut(&card, *card.lut.borrow()).unwrap();
+		assert_eq!(original, None);
+		card
+			.set_blob(handle(), property(), Some(&[5, 6, 7, 8]))
+			.unwrap();
+
+		card
+			.set_blob(handle(), property(), original.as_deref())
+			.unwrap();
+		assert_eq!(card.lut(), None);
+		assert_eq!(*card.lut.borrow(), 0);
+	}
+
+	#[test]
+	fn resample_channels() {
+		let channel = [0, 1000, 3000];
+		assert_eq!(resample(&channel, 3).collect::<Vec<_>>(), channel);
+		// A LUT that is larger than the ramps, as is common.
+		assert_eq!(
+			resample(&channel, 5).collect::<Vec<_>>(),
+			[0, 500, 1000, 2000, 3000]
+		);
+		assert_eq!(
+			resample(&[0, 500, 1000, 2000, 3000], 3).collect::<Vec<_>>(),
+			channel
+		);
+		assert_eq!(resample(&channel, 1).collect::<Vec<_>>(), [0]);
+	}
+
+	#[test]
+	fn parse_uevents() {
+		let hotplug = b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0\
+			DEVPATH=/devices/pci0000:00/0000:00:02.0/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0\
+			DEVNAME=/dev/dri/card0\0DEVTYPE=drm_minor\0SEQNUM=4242\0MAJOR=226\0MINOR=0\0";
+		assert!(is_drm_hotplug(hotplug));
+
+		// A DRM uevent that is not a hotplug, and a hotplug of another subsystem.
+		let change = b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0\
+			SUBSYSTEM=drm\0DEVNAME=/dev/dri/card0\0";
+		assert!(!is_drm_hotplug(change));
+		let other = b"change@/devices/platform/dock.0\0ACTION=change\0SUBSYSTEM=platform\0HOTPLUG=1\0";
+		assert!(!is_drm_hotplug(other));
+		// Only `HOTPLUG=1` counts.
+		assert!(!is_drm_hotplug(b"SUBSYSTEM=drm\0HOTPLUG=0\0"));
+	}
+}
@@ -0,0 +1,76 @@
+//! The `dry-run` backend, which prints the configs and checksums of the ramps to standard output instead of setting the gamma.
+
+use std::io::Write;
+
+use crate::backend::{GammaBackend, OutputId, OutputInfo};
+use crate::color::{Config, Ramps};
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::Event;
+
+/// The ramp size of the virtual output, which is the most common size.
+const RAMP_SIZE: usize = 256;
+/// The name of the virtual output, e.g., for `--icc-profile dry-run=PATH`.
+const OUTPUT_NAME: &str = "dry-run";
+
+/// Has a single virtual output.
+pub struct Backend {
+	output: Box<dyn Write>,
+}
+
+impl Backend {
+	pub fn new(event_send: &EventSender) -> Self {
+		Self::with_output(event_send, Box::new(std::io::stdout()))
+	}
+
+	/// Prints to `output` instead of standard output.
+	pub fn with_output(event_send: &EventSender, output: Box<dyn Write>) -> Self {
+		_ = event_send.send(Event::AddOutput(OutputInfo {
+			id: 0,
+			name: Some(OUTPUT_NAME.into()),
+			description: OUTPUT_NAME.into(),
+			ramp_size: RAMP_SIZE,
+		}));
+		_ = event_send.send(Event::OutputsEnumerated);
+		Self { output }
+	}
+}
+
+impl GammaBackend for Backend {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_config(&mut self, time: time::Time, config: Config) -> Result<(), Error> {
+		writeln!(
+			self.output,
+			"{:02}:{:02} {config:?}",
+			time.hour(),
+			time.minute()
+		)
+		.map_err(Error::io("could not print the config"))
+	}
+
+	fn set_gamma(&mut self, _output: OutputId, ramps: &Ramps) -> Result<(), Error> {
+		writeln!(
+			self.output,
+			"  {OUTPUT_NAME}: ramps {:016x}",
+			fnv1a(ramps.as_bytes())
+		)
+		.map_err(Error::io("could not print the ramps"))
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self
+			.output
+			.flush()
+			.map_err(Error::io("could not flush the output"))
+	}
+}
+
+/// The 64-bit FNV-1a hash, which is stable across versions and platforms, unlike `std::hash`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+		(hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+	})
+}
@@ -0,0 +1,107 @@
+//! Fatal errors of the daemon, with user-facing messages and exit codes.
+
+use std::fmt::{self, Display, Formatter};
+
+use wayland_client::backend::WaylandError;
+use wayland_client::{ConnectError, DispatchError};
+
+#[derive(Debug)]
+pub enum Error {
+	/// Invalid configuration, e.g., a calibration file that could not be loaded.
+	Config(String),
+	WaylandConnect(ConnectError),
+	X11Connect(x11rb::errors::ConnectError),
+	/// The display server does not support a required protocol, given by its interface or extension name.
+	MissingProtocol(&'static str),
+	/// The compositor refused to give us control of an output's gamma.
+	GammaControlFailed {
+		output: String,
+	},
+	/// The connection to the compositor broke.
+	Wayland(DispatchError),
+	/// The connection to the X server broke, or it rejected a request.
+	X11(x11rb::errors::ReplyError),
+	Dbus {
+		context: &'static str,
+		error: zbus::Error,
+	},
+	TimeZone(String),
+	Io {
+		context: &'static str,
+		error: std::io::Error,
+	},
+}
+
+impl Error {
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Self::Io { .. } => 1,
+			// The same as for invalid arguments.
+			Self::Config(_) => 2,
+			Self::WaylandConnect(_) | Self::X11Connect(_) | Self::MissingProtocol(_) => 3,
+			Self::GammaControlFailed { .. } => 4,
+			Self::Wayland(_) | Self::X11(_) => 5,
+			Self::Dbus { .. } | Self::TimeZone(_) => 6,
+		}
+	}
+
+	pub fn dbus(context: &'static str) -> impl FnOnce(zbus::Error) -> Self {
+		move |error| Self::Dbus { context, error }
+	}
+
+	pub fn io(context: &'static str) -> impl FnOnce(std::io::Error) -> Self {
+		move |error| Self::Io { context, error }
+	}
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Config(message) | Self::TimeZone(message) => f.write_str(message),
+			Self::WaylandConnect(error) => write!(
+				f,
+				"could not connect to the Wayland compositor (is WAYLAND_DISPLAY set?): {error}"
+			),
+			Self::X11Connect(error) => {
+				write!(f, "could not connect to the X server (is DISPLAY set?): {error}")
+			}
+			Self::MissingProtocol(interface) => {
+				write!(f, "your display server does not support {interface}")
+			}
+			Self::GammaControlFailed { output } => write!(
+				f,
+				"could not control the gamma of output {output}; is another program such as gammastep or wlsunset running?"
+			),
+			Self::Wayland(error) => write!(f, "lost the connection to the Wayland compositor: {error}"),
+			Self::X11(error) => write!(f, "X server error: {error}"),
+			Self::Dbus { context, error } => write!(f, "{context}: {error}"),
+			Self::Io { context, error } => write!(f, "{context}: {error}"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<DispatchError> for Error {
+	fn from(error: DispatchError) -> Self {
+		Self::Wayland(error)
+	}
+}
+
+impl From<WaylandError> for Error {
+	fn from(error: WaylandError) -> Self {
+		Self::Wayland(error.into())
+	}
+}
+
+impl From<x11rb::errors::ReplyError> for Error {
+	fn from(error: x11rb::errors::ReplyError) -> Self {
+		Self::X11(error)
+	}
+}
+
+impl From<x11rb::errors::ConnectionError> for Error {
+	fn from(error: x11rb::errors::ConnectionError) -> Self {
+		Self::X11(error.into())
+	}
+}
@@ -0,0 +1,292 @@
+//! The daemon's event loop, which waits on all event sources at once with `poll(2)`.
+//!
+//! Wayland, the gamma backend, signals (through a `signalfd`), the regular update timer (a `timerfd`),
+//! the control socket, and the ambient light sensor are all handled on the main thread.
+//! The exception is D-Bus: zbus runs its own executor and does not give access to its socket,
+//! so all D-Bus signals are received on a single other thread, which wakes up the loop through the `EventSender`.
+//!
+//! Events are returned in the order they were sent. Within one wakeup, the sources are handled in a fixed order:
+//! the gamma backend, Wayland, signals, the update timer, the D-Bus thread, the control socket, and finally the ambient light sensor.
+
+use std::io::{Read as _, Write as _};
+use std::os::fd::{AsFd as _, AsRawFd as _};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{Receiver, SendError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+use wayland_client::backend::{ReadEventsGuard, WaylandError};
+use wayland_client::Connection;
+
+use crate::backend::GammaBackend;
+use crate::error::Error;
+use crate::wayland::WaylandSource;
+use crate::{ambient, control, Event};
+
+const SIGNALS: [Signal; 4] = [
+	Signal::SIGUSR1,
+	Signal::SIGUSR2,
+	Signal::SIGINT,
+	Signal::SIGTERM,
+];
+
+/// Sends events to the event loop, waking it up if it is waiting.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+	events: Sender<Event>,
+	waker: Arc<UnixStream>,
+}
+
+impl EventSender {
+	/// Fails if the event loop is gone.
+	pub fn send(&self, event: Event) -> Result<(), SendError<()>> {
+		self.events.send(event).map_err(|_| SendError(()))?;
+		// If the socket is full, the loop is going to wake up anyway.
+		_ = (&*self.waker).write(&[0]);
+		Ok(())
+	}
+
+	/// A sender that is not connected to an event loop, with the receiving end of its events.
+	#[cfg(test)]
+	pub fn channel() -> (Self, Receiver<Event>) {
+		let (events, event_recv) = std::sync::mpsc::channel();
+		let (_wake, waker) = UnixStream::pair().unwrap();
+		waker.set_nonblocking(true).unwrap();
+		let sender = Self {
+			events,
+			waker: Arc::new(waker),
+		};
+		(sender, event_recv)
+	}
+}
+
+pub struct EventLoop {
+	/// `None` if not connected to a Wayland compositor, e.g., with the X11 backend.
+	connection: Option<Connection>,
+	event_send: EventSender,
+	event_recv: Receiver<Event>,
+	/// Readable when another thread sent an event.
+	wake: UnixStream,
+	signals: SignalFd,
+	/// Expires at every full minute of the real-time clock, and when that clock is changed.
+	update_timer: TimerFd,
+	wayland_sources: Vec<Box<dyn WaylandSource>>,
+	control: Option<control::Listener>,
+	ambient: Option<ambient::Monitor>,
+}
+
+impl EventLoop {
+	/// Blocks the signals that are handled by the loop in the calling thread,
+	/// so this must be called before any other threads are spawned, since they inherit the signal mask.
+	pub fn new(connection: Option<Connection>) -> Result<Self, Error> {
+		let mut mask = SigSet::empty();
+		for signal in SIGNALS {
+			mask.add(signal);
+		}
+		mask
+			.thread_block()
+			.map_err(|errno| Error::io("could not block signals")(errno.into()))?;
+		let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+			.map_err(|errno| Error::io("could not create a signalfd")(errno.into()))?;
+
+		let update_timer = TimerFd::new(
+			ClockId::CLOCK_REALTIME,
+			TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+		)
+		.map_err(|errno| Error::io("could not create a timerfd")(errno.into()))?;
+		arm_update_timer(&update_timer)?;
+
+		let (wake, waker) = UnixStream::pair().map_err(Error::io("could not create a socket pair"))?;
+		wake
+			.set_nonblocking(true)
+			.and_then(|()| waker.set_nonblocking(true))
+			.map_err(Error::io("could not create a socket pair"))?;
+		let (events, event_recv) = std::sync::mpsc::channel();
+
+		Ok(Self {
+			connection,
+			event_send: EventSender {
+				events,
+				waker: Arc::new(waker),
+			},
+			event_recv,
+			wake,
+			signals,
+			update_timer,
+			wayland_sources: Vec::new(),
+			control: None,
+			ambient: None,
+		})
+	}
+
+	pub fn sender(&self) -> EventSender {
+		self.event_send.clone()
+	}
+
+	pub fn add_wayland_source(&mut self, source: Box<dyn WaylandSource>) {
+		self.wayland_sources.push(source);
+	}
+
+	pub fn set_control(&mut self, listener: control::Listener) {
+		self.control = Some(listener);
+	}
+
+	pub fn set_ambient(&mut self, monitor: ambient::Monitor) {
+		self.ambient = Some(monitor);
+	}
+
+	/// Returns the next event, or `Event::Update` if there was none for `timeout`.
+	pub fn next(
+		&mut self,
+		backend: &mut dyn GammaBackend,
+		timeout: Option<Duration>,
+	) -> Result<Event, Error> {
+		let deadline = timeout.map(|timeout| Instant::now() + timeout);
+		loop {
+			backend.dispatch_pending()?;
+			for source in &mut self.wayland_sources {
+				source.dispatch_pending()?;
+			}
+			match self.event_recv.try_recv() {
+				Ok(event) => return Ok(event),
+				Err(TryRecvError::Empty) => {}
+				Err(TryRecvError::Disconnected) => unreachable!("we hold a sender"),
+			}
+
+			let now = Instant::now();
+			if deadline.is_some_and(|deadline| deadline <= now) {
+				return Ok(Event::Update);
+			}
+			if let Some(ambient) = &mut self.ambient {
+				if ambient.next_poll() <= now {
+					ambient.poll(&self.event_send);
+					continue;
+				}
+			}
+			let wake_at = [
+				deadline,
+				self.ambient.as_ref().map(ambient::Monitor::next_poll),
+				self
+					.control
+					.as_ref()
+					.and_then(control::Listener::next_deadline),
+			]
+			.into_iter()
+			.flatten()
+			.min();
+			let poll_timeout = wake_at.map_or(-1, |wake_at| {
+				// Round up so that the deadline has passed when `poll` returns.
+				let millis = (wake_at - now).as_micros().div_ceil(1000);
+				i32::try_from(millis).unwrap_or(i32::MAX)
+			});
+
+			let guard = match &self.connection {
+				Some(connection) => {
+					connection.flush()?;
+					// `None` means that there are already events to dispatch.
+					let Some(guard) = connection.prepare_read() else {
+						continue;
+					};
+					Some(guard)
+				}
+				None => None,
+			};
+			let ([wayland, _backend, signals, update_timer, wake], control) = {
+				let fds = [
+					guard.as_ref().map(ReadEventsGuard::connection_fd),
+					backend.fd(),
+					Some(self.signals.as_fd()),
+					Some(self.update_timer.as_fd()),
+					Some(self.wake.as_fd()),
+				];
+				// The listener, followed by the clients that have not sent their command yet.
+				let control_fds = self
+					.control
+					.iter()
+					.flat_map(control::Listener::fds)
+					.collect::<Vec<_>>();
+				let mut poll_fds = fds
+					.iter()
+					.flatten()
+					.chain(&control_fds)
+					.map(|fd| PollFd::new(fd, PollFlags::POLLIN))
+					.collect::<Vec<_>>();
+				match poll(&mut poll_fds, poll_timeout) {
+					Ok(_) | Err(Errno::EINTR) => {}
+					Err(errno) => return Err(Error::io("could not poll")(errno.into())),
+				}
+				let mut ready = poll_fds.iter().map(|fd| fd.any().unwrap_or(true));
+				// The backend's events are dispatched at the top of the loop.
+				let fds = fds.map(|fd| fd.is_some() && ready.next().unwrap_or(false));
+				// Handling the clients also drops those that timed out, which is cheap, so do it on any timeout.
+				let control =
+					ready.any(|ready| ready) || wake_at.is_some_and(|wake_at| wake_at <= Instant::now());
+				(fds, control)
+			};
+
+			if let Some(guard) = guard {
+				if wayland {
+					match guard.read() {
+						Ok(_) => {}
+						Err(WaylandError::Io(error)) if error.kind() == std::io::ErrorKind::WouldBlock => {}
+						Err(error) => return Err(error.into()),
+					}
+				}
+			}
+			if signals {
+				self.handle_signals()?;
+			}
+			if update_timer {
+				// The read fails with `ECANCELED` if the clock was changed, which is also a reason to update.
+				_ = nix::unistd::read(self.update_timer.as_fd().as_raw_fd(), &mut [0; 8]);
+				arm_update_timer(&self.update_timer)?;
+				_ = self.event_send.send(Event::Update);
+			}
+			if wake {
+				// The events themselves are in the channel.
+				while matches!((&self.wake).read(&mut [0; 64]), Ok(1..)) {}
+			}
+			if let (true, Some(control)) = (control, &mut self.control) {
+				control.handle(&self.event_send);
+			}
+		}
+	}
+
+	fn handle_signals(&mut self) -> Result<(), Error> {
+		while let Some(info) = self
+			.signals
+			.read_signal()
+			.map_err(|errno| Error::io("could not read from the signalfd")(errno.into()))?
+		{
+			let event = match Signal::try_from(info.ssi_signo.cast_signed()) {
+				Ok(Signal::SIGUSR1) => Event::SetDimmed(true),
+				Ok(Signal::SIGUSR2) => Event::SetDimmed(false),
+				Ok(Signal::SIGINT | Signal::SIGTERM) => Event::Quit,
+				_ => continue,
+			};
+			_ = self.event_send.send(event);
+		}
+		Ok(())
+	}
+}
+
+/// Arms the timer for the next full minute.
+fn arm_update_timer(timer: &TimerFd) -> Result<(), Error> {
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs();
+	let next_minute = (now / 60 + 1) * 60;
+	timer
+		.set(
+			Expiration::OneShot(TimeSpec::new(next_minute.cast_signed(), 0)),
+			TimerSetTimeFlags::TFD_TIMER_ABSTIME | TimerSetTimeFlags::TFD_TIMER_CANCEL_ON_SET,
+		)
+		.map_err(|errno| Error::io("could not set the update timer")(errno.into()))
+}
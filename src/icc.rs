@@ -0,0 +1,236 @@
+//! Parsing of the `vcgt` (video card gamma table) tag from ICC profiles.
+//!
+//! The `vcgt` tag is not part of the ICC specification, but it is written by all common calibration tools.
+//! It contains the curves that should be loaded into the gamma ramps for the profile to be accurate.
+
+use std::path::Path;
+
+use crate::color::Curves;
+
+/// The number of samples used to tabulate formula-type `vcgt` tags.
+const FORMULA_SAMPLES: usize = 1024;
+
+const HEADER_SIZE: usize = 128;
+const TAG_ENTRY_SIZE: usize = 12;
+
+pub fn load_vcgt(path: &Path) -> Result<Curves, String> {
+	let data =
+		std::fs::read(path).map_err(|error| format!("could not read {}: {error}", path.display()))?;
+	parse_vcgt(&data).map_err(|error| {
+		format!(
+			"could not load calibration from {}: {error}",
+			path.display()
+		)
+	})
+}
+
+pub fn parse_vcgt(profile: &[u8]) -> Result<Curves, String> {
+	if profile.get(36..40) != Some(b"acsp") {
+		return Err("not an ICC profile".into());
+	}
+	let tag_count = read_u32(profile, HEADER_SIZE)? as usize;
+	let tag = (0..tag_count)
+		.map(|index| HEADER_SIZE + 4 + index * TAG_ENTRY_SIZE)
+		.find(|&entry| profile.get(entry..entry + 4) == Some(b"vcgt"))
+		.ok_or("the profile has no vcgt tag")?;
+	let offset = read_u32(profile, tag + 4)? as usize;
+	let size = read_u32(profile, tag + 8)? as usize;
+	let data = profile
+		.get(offset..offset.saturating_add(size))
+		.ok_or("the vcgt tag is out of bounds")?;
+
+	if data.get(0..4) != Some(b"vcgt") {
+		return Err("the vcgt tag has the wrong type signature".into());
+	}
+	match read_u32(data, 8)? {
+		0 => parse_table(&data[12..]),
+		1 => parse_formula(&data[12..]),
+		other => Err(format!("unknown vcgt type {other}")),
+	}
+}
+
+fn parse_table(data: &[u8]) -> Result<Curves, String> {
+	let channels = usize::from(read_u16(data, 0)?);
+	let entry_count = usize::from(read_u16(data, 2)?);
+	let entry_size = usize::from(read_u16(data, 4)?);
+
+	let read_entry = |index: usize| -> Result<f32, String> {
+		let position = 6 + index * entry_size;
+		Ok(match entry_size {
+			1 => f32::from(*data.get(position).ok_or("vcgt table is truncated")?) / f32::from(u8::MAX),
+			2 => f32::from(read_u16(data, position)?) / f32::from(u16::MAX),
+			_ => return Err(format!("unsupported vcgt entry size {entry_size}")),
+		})
+	};
+	let read_channel = |channel: usize| -> Result<Box<[f32]>, String> {
+		(0..entry_count)
+			.map(|index| read_entry(channel * entry_count + index))
+			.collect()
+	};
+
+	let [red, green, blue] = match channels {
+		1 => {
+			let curve = read_channel(0)?;
+			[curve.clone(), curve.clone(), curve]
+		}
+		3 => [read_channel(0)?, read_channel(1)?, read_channel(2)?],
+		_ => return Err(format!("unsupported vcgt channel count {channels}")),
+	};
+	Curves::new(red, green, blue).ok_or_else(|| "the vcgt table is empty".into())
+}
+
+fn parse_formula(data: &[u8]) -> Result<Curves, String> {
+	let read_channel = |channel: usize| -> Result<Box<[f32]>, String> {
+		let base = channel * 12;
+		let gamma = read_s15_fixed16(data, base)?;
+		let min = read_s15_fixed16(data, base + 4)?;
+		let max = read_s15_fixed16(data, base + 8)?;
+		Ok(
+			(0..FORMULA_SAMPLES)
+				.map(|index| {
+					let x = index as f32 / (FORMULA_SAMPLES - 1) as f32;
+					min + (max - min) * x.powf(gamma)
+				})
+				.collect(),
+		)
+	};
+	Curves::new(read_channel(0)?, read_channel(1)?, read_channel(2)?)
+		.ok_or_else(|| "the vcgt formula is empty".into())
+}
+
+fn read_u16(data: &[u8], position: usize) -> Result<u16, String> {
+	data
+		.get(position..position + 2)
+		.map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+		.ok_or_else(|| "unexpected end of profile".into())
+}
+
+fn read_u32(data: &[u8], position: usize) -> Result<u32, String> {
+	data
+		.get(position..position + 4)
+		.map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+		.ok_or_else(|| "unexpected end of profile".into())
+}
+
+fn read_s15_fixed16(data: &[u8], position: usize) -> Result<f32, String> {
+	read_u32(data, position).map(|raw| raw.cast_signed() as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_vcgt, FORMULA_SAMPLES, HEADER_SIZE};
+
+	/// Wraps a `vcgt` tag (without its type signature and reserved bytes) in a minimal profile.
+	fn profile(vcgt: &[u8]) -> Vec<u8> {
+		let mut profile = vec![0; HEADER_SIZE];
+		profile[36..40].copy_from_slice(b"acsp");
+		let offset = HEADER_SIZE + 4 + 12;
+		let size = 8 + vcgt.len();
+		profile.extend_from_slice(&1u32.to_be_bytes());
+		profile.extend_from_slice(b"vcgt");
+		profile.extend_from_slice(&(offset as u32).to_be_bytes());
+		profile.extend_from_slice(&(size as u32).to_be_bytes());
+		profile.extend_from_slice(b"vcgt\0\0\0\0");
+		profile.extend_from_slice(vcgt);
+		profile
+	}
+
+	#[test]
+	fn table() {
+		#[rustfmt::skip]
+		let vcgt = [
+			0, 0, 0, 0, // Table
+			0, 3, // Channels
+			0, 2, // Entries per channel
+			0, 2, // Bytes per entry
+			0x00, 0x00, 0xff, 0xff, // Red
+			0x00, 0x00, 0x80, 0x00, // Green
+			0xff, 0xff, 0x00, 0x00, // Blue
+		];
+		let curves = parse_vcgt(&profile(&vcgt)).unwrap();
+		assert_eq!(
+			curves.channels(),
+			[&[0.0, 1.0][..], &[0.0, 32768.0 / 65535.0], &[1.0, 0.0]]
+		);
+	}
+
+	#[test]
+	fn shared_table() {
+		#[rustfmt::skip]
+		let vcgt = [
+			0, 0, 0, 0, // Table
+			0, 1, // Channels
+			0, 3, // Entries per channel
+			0, 1, // Bytes per entry
+			0x00, 0x33, 0xff,
+		];
+		let curves = parse_vcgt(&profile(&vcgt)).unwrap();
+		assert_eq!(curves.channels(), [&[0.0, 0.2, 1.0][..]; 3]);
+	}
+
+	#[test]
+	fn formula() {
+		#[rustfmt::skip]
+		let vcgt = [
+			0, 0, 0, 1, // Formula
+			0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // Red: gamma 2, 0 to 1
+			0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0xc0, 0x00, // Green: gamma 1, 0.5 to 0.75
+			0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // Blue: identity
+		];
+		let curves = parse_vcgt(&profile(&vcgt)).unwrap();
+		let [red, green, blue] = curves.channels();
+		assert_eq!(red.len(), FORMULA_SAMPLES);
+		assert_eq!((red[0], red[FORMULA_SAMPLES - 1]), (0.0, 1.0));
+		let middle = FORMULA_SAMPLES / 2;
+		let x = middle as f32 / (FORMULA_SAMPLES - 1) as f32;
+		assert!((red[middle] - x * x).abs() < 1e-6);
+		assert_eq!((green[0], green[FORMULA_SAMPLES - 1]), (0.5, 0.75));
+		assert!((blue[middle] - x).abs() < 1e-6);
+	}
+
+	#[test]
+	fn truncated() {
+		#[rustfmt::skip]
+		let vcgt = [
+			0, 0, 0, 0, // Table
+			0, 3, // Channels
+			0, 4, // Entries per channel, but only 2 are there
+			0, 2, // Bytes per entry
+			0x00, 0x00, 0xff, 0xff,
+		];
+		assert!(parse_vcgt(&profile(&vcgt)).is_err());
+		// A formula with only one channel.
+		assert!(parse_vcgt(&profile(&[0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0])).is_err());
+		// The tag claims to extend past the end of the profile.
+		let mut profile = profile(&[0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 0]);
+		profile.truncate(profile.len() - 4);
+		assert_eq!(
+			parse_vcgt(&profile).unwrap_err(),
+			"the vcgt tag is out of bounds"
+		);
+		assert!(parse_vcgt(&profile[..100]).is_err());
+	}
+
+	#[test]
+	fn unsupported() {
+		assert_eq!(
+			parse_vcgt(&profile(&[0, 0, 0, 2])).unwrap_err(),
+			"unknown vcgt type 2"
+		);
+		assert_eq!(
+			parse_vcgt(&profile(&[0, 0, 0, 0, 0, 3, 0, 1, 0, 4, 0, 0, 0, 0])).unwrap_err(),
+			"unsupported vcgt entry size 4"
+		);
+		assert_eq!(
+			parse_vcgt(&profile(&[0, 0, 0, 0, 0, 2, 0, 1, 0, 1, 0, 0])).unwrap_err(),
+			"unsupported vcgt channel count 2"
+		);
+		let mut not_vcgt = profile(&[0, 0, 0, 0]);
+		not_vcgt[132..136].copy_from_slice(b"desc");
+		assert_eq!(
+			parse_vcgt(&not_vcgt).unwrap_err(),
+			"the profile has no vcgt tag"
+		);
+		assert_eq!(parse_vcgt(&[0; 200]).unwrap_err(), "not an ICC profile");
+	}
+}
@@ -0,0 +1,85 @@
+//! Rules that temporarily override the scheduled config depending on the focused window.
+
+use std::str::FromStr;
+
+/// The currently focused (activated) toplevel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Focus {
+	pub app_id: String,
+	pub title: String,
+	pub fullscreen: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+	/// The app ID is exactly this.
+	AppId(String),
+	/// The title contains this.
+	Title(String),
+	Fullscreen,
+}
+
+impl Condition {
+	fn matches(&self, focus: &Focus) -> bool {
+		match self {
+			Self::AppId(app_id) => focus.app_id == *app_id,
+			Self::Title(title) => focus.title.contains(title.as_str()),
+			Self::Fullscreen => focus.fullscreen,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	/// Remove the tint entirely, keeping only the brightness and filters.
+	Neutral,
+	/// Stop following the schedule, keeping the temperature from when the rule started matching.
+	Pause,
+}
+
+/// A rule such as `app_id=darktable:neutral` or `fullscreen:pause`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+	condition: Condition,
+	action: Action,
+}
+
+impl FromStr for Rule {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Self, Self::Err> {
+		let (condition, action) = input
+			.rsplit_once(':')
+			.ok_or("expected `CONDITION:ACTION`")?;
+		let condition = if condition == "fullscreen" {
+			Condition::Fullscreen
+		} else if let Some(app_id) = condition.strip_prefix("app_id=") {
+			Condition::AppId(app_id.into())
+		} else if let Some(title) = condition.strip_prefix("title=") {
+			Condition::Title(title.into())
+		} else {
+			return Err(format!(
+				"unknown condition {condition:?}, expected `app_id=...`, `title=...`, or `fullscreen`"
+			));
+		};
+		let action = match action {
+			"neutral" => Action::Neutral,
+			"pause" => Action::Pause,
+			_ => {
+				return Err(format!(
+					"unknown action {action:?}, expected `neutral` or `pause`"
+				))
+			}
+		};
+		Ok(Self { condition, action })
+	}
+}
+
+/// Returns the action of the first rule that matches the focused window, if any.
+pub fn action(rules: &[Rule], focus: Option<&Focus>) -> Option<Action> {
+	let focus = focus?;
+	rules
+		.iter()
+		.find(|rule| rule.condition.matches(focus))
+		.map(|rule| rule.action)
+}
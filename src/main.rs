@@ -1,3 +1,5 @@
+// Some of the denied lints have since been removed from the compiler, which warns about them.
+#![allow(renamed_and_removed_lints)]
 #![deny(
 	absolute_paths_not_starting_with_crate,
 	keyword_idents,
@@ -22,147 +24,881 @@
 )]
 #![forbid(unsafe_code)]
 
-use std::sync::mpsc::SyncSender;
+use std::io::Write as _;
+use std::ops::ControlFlow;
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
 
-use signal_hook::consts::signal;
-use signal_hook::iterator::Signals;
-use time::ext::NumericalDuration;
-use time::{Duration, Time};
+use clap::{CommandFactory as _, Parser as _};
+use futures_lite::StreamExt as _;
+use time::{Duration, OffsetDateTime, Time};
 use wayland_client::Connection;
 
-use crate::color::Config;
-use crate::util::{lerp, Ignored};
-use crate::wayland::GammaControl;
+use crate::backend::OutputId;
+use crate::cli::Args;
+use crate::color::{Config, Curves, Filters, Tint};
+use crate::error::Error;
+use crate::event_loop::{EventLoop, EventSender};
+use crate::util::lerp;
 
+mod ambient;
+mod backend;
+mod backlight;
+mod cli;
+mod clock;
 mod color;
+mod control;
 mod dbus_time;
+mod dconf;
+mod drm;
+mod dry_run;
+mod error;
+mod event_loop;
+mod icc;
+mod inhibit;
+mod night_light;
+mod notify;
+mod plot;
+mod preview;
+mod query;
+mod ramp_file;
+mod session;
+mod status;
+#[cfg(test)]
+mod test_compositor;
+#[cfg(test)]
+mod test_dbus;
 mod util;
 mod wayland;
+mod wlr;
+mod x11;
 
 #[derive(Debug)]
 pub enum Event {
-	AddOutput(GammaControl),
-	RemoveOutput { output_registry_name: u32 },
+	AddOutput(backend::OutputInfo),
+	#[rustfmt::skip] // Single-line form.
+	RemoveOutput { id: OutputId },
+	/// All outputs that existed at startup have been added.
+	OutputsEnumerated,
 	Update,
 	SetDimmed(bool),
+	SetIdle(bool),
+	/// Whether a logind idle inhibitor is active, in which case idling does not dim.
+	SetIdleInhibited(bool),
+	SetLocked(bool),
+	Control(control::Command),
+	/// The ambient light level, from 0.0 (dark) to 1.0 (bright).
+	SetAmbientLight(f32),
+	/// The focused window changed, for `--inhibit` rules.
+	SetFocus(Option<inhibit::Focus>),
+	/// A `rustshift ctl watch` client connected.
+	Watch(UnixStream),
+	/// An essential event source failed.
+	Fatal(Error),
+	Quit,
 }
 
-fn update_regularly(event_send: &SyncSender<Event>) {
-	let interval = std::time::Duration::from_secs(60);
-	loop {
-		std::thread::sleep(interval);
-		if event_send.send(Event::Update).is_err() {
-			break;
+const DAYTIME_START: Time = hour_minute(7, 45);
+const DAYTIME_END: Time = hour_minute(19, 45);
+/// How long the transitions take, starting at `DAYTIME_START` and `DAYTIME_END`.
+const TRANSITION_TIME: Duration = Duration::minutes(30);
+
+const fn hour_minute(hour: u8, minute: u8) -> Time {
+	match Time::from_hms(hour, minute, 0) {
+		Ok(time) => time,
+		Err(_) => panic!("invalid time"),
+	}
+}
+
+/// Returns the progress of the transition at `time` from 0.0 to 1.0, if in one,
+/// and whether it is daytime (after the transition, if in one).
+fn schedule_position(time: Time) -> (Option<f32>, bool) {
+	let progress = |start: Time| {
+		let diff = time - start;
+		(diff > Duration::ZERO && diff < TRANSITION_TIME)
+			.then(|| diff.as_seconds_f32() / TRANSITION_TIME.as_seconds_f32())
+	};
+	let daytime = time >= DAYTIME_START && time <= DAYTIME_END;
+	(progress(DAYTIME_START).or(progress(DAYTIME_END)), daytime)
+}
+
+/// Returns when the next transition after `time` starts.
+fn next_transition(time: Time) -> Time {
+	if time >= DAYTIME_START && time < DAYTIME_END {
+		DAYTIME_END
+	} else {
+		DAYTIME_START
+	}
+}
+
+/// Returns when the last transition at or before `time` started, which may have been the day before.
+fn previous_transition(time: Time) -> Time {
+	if time >= DAYTIME_START && time < DAYTIME_END {
+		DAYTIME_START
+	} else {
+		DAYTIME_END
+	}
+}
+
+/// Returns when the last transition at or before `now` started and when the next one starts, in local time.
+///
+/// `now` must be in the offset that the schedule follows (see `clock::TimeZone::to_schedule`).
+fn surrounding_transitions(
+	time_zone: &clock::TimeZone,
+	now: OffsetDateTime,
+) -> Result<(OffsetDateTime, OffsetDateTime), Error> {
+	let time = now.time();
+	let mut previous = now.replace_time(previous_transition(time));
+	if previous > now {
+		previous -= Duration::DAY;
+	}
+	let mut next = now.replace_time(next_transition(time));
+	if next <= now {
+		next += Duration::DAY;
+	}
+	Ok((time_zone.to_local(previous)?, time_zone.to_local(next)?))
+}
+
+/// `dim_level` is from 0.0 (not dimmed) to 1.0 (fully dimmed).
+fn get_config(args: &Args, time: Time, dim_level: f32) -> Config {
+	let brightness = lerp(1.0, args.dim_brightness, dim_level);
+
+	// The temperatures were validated when parsing the arguments.
+	let period_config = |temperature, tint| {
+		Config::new(temperature, brightness)
+			.unwrap()
+			.with_white_point_model(args.white_point_model)
+			.with_tint(tint)
+	};
+	let day = period_config(args.day_temperature, args.day_tint);
+	let night = period_config(args.night_temperature, args.night_tint);
+
+	match schedule_position(time) {
+		(Some(progress), true) => Config::lerp(night, day, progress),
+		(Some(progress), false) => Config::lerp(day, night, progress),
+		(None, true) => day,
+		(None, false) => night,
+	}
+}
+
+/// Like `tracing_subscriber::fmt::init`, but logs to standard error,
+/// since standard output is for `dump-ramps`, `preview`, and dry runs.
+fn init_logging() {
+	use tracing_subscriber::filter::Targets;
+	use tracing_subscriber::layer::SubscriberExt as _;
+	use tracing_subscriber::util::SubscriberInitExt as _;
+
+	let default = || Targets::new().with_default(tracing::Level::INFO);
+	let targets = std::env::var("RUST_LOG").map_or_else(
+		|_| default(),
+		|var| var.parse().unwrap_or_else(|_| default()),
+	);
+	tracing_subscriber::registry()
+		.with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+		.with(targets)
+		.init();
+}
+
+fn main() {
+	init_logging();
+
+	let args = Args::parse();
+	if let Err(error) = args.validate() {
+		Args::command()
+			.error(clap::error::ErrorKind::ArgumentConflict, error)
+			.exit();
+	}
+
+	if let Some(command) = &args.command {
+		let result = match command {
+			cli::Command::Ctl {
+				command: cli::CtlCommand::Watch { format },
+			} => status::watch(*format),
+			cli::Command::Ctl {
+				command: cli::CtlCommand::Other(command),
+			} => control::send(&command.join(" ")),
+			cli::Command::DumpRamps(dump_args) => ramp_file::dump(&args, dump_args),
+			cli::Command::Plot(plot_args) => plot::plot(&args, plot_args),
+			cli::Command::Query(query_args) => query::query(&args, query_args),
+			cli::Command::Preview(preview_args) => {
+				// Like the daemon, this can fail in many ways, which have their own exit codes.
+				if let Err(error) = preview::run(&args, preview_args) {
+					eprintln!("error: {error}");
+					std::process::exit(error.exit_code());
+				}
+				Ok(())
+			}
+		};
+		if let Err(error) = result {
+			eprintln!("error: {error}");
+			std::process::exit(1);
 		}
+		return;
+	}
+
+	if let Err(error) = run_daemon(&args) {
+		eprintln!("error: {error}");
+		std::process::exit(error.exit_code());
 	}
 }
 
-fn signal_handler(event_send: &SyncSender<Event>) {
-	let mut signals = Signals::new([signal::SIGUSR1, signal::SIGUSR2]).unwrap();
-	for signal in &mut signals {
-		let event = match signal {
-			signal::SIGUSR1 => Event::SetDimmed(true),
-			signal::SIGUSR2 => Event::SetDimmed(false),
-			_ => continue,
+/// Runtime state that affects the config, apart from the time.
+#[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)] // They are independent inputs.
+struct State {
+	dimmed: bool,
+	idle: bool,
+	idle_inhibited: bool,
+	locked: bool,
+	/// Paused with `rustshift ctl pause`.
+	paused: bool,
+	/// Fades from 0.0 (not dimmed) to 1.0 (fully dimmed) when `dimmed`, `idle`, or `locked` change.
+	dim_level: f32,
+	/// When the dim level was last advanced, if it is currently fading.
+	dim_fade_step: Option<Instant>,
+	filters: Filters,
+	/// Set with `rustshift ctl tint`, replacing the scheduled white point.
+	tint: Option<Tint>,
+	/// From 0.0 (dark) to 1.0 (bright), if known.
+	ambient_light: Option<f32>,
+	focus: Option<inhibit::Focus>,
+	/// The schedule time when the schedule was paused, either manually or by an `--inhibit` rule.
+	paused_time: Option<Time>,
+}
+
+/// How often the config is updated while fading.
+const FADE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+impl State {
+	fn new(args: &Args) -> Self {
+		Self {
+			dimmed: false,
+			idle: false,
+			idle_inhibited: false,
+			locked: false,
+			paused: false,
+			dim_level: 0.0,
+			dim_fade_step: None,
+			filters: Filters {
+				invert: args.invert,
+				reduce_color: args.reduce_color,
+			},
+			tint: None,
+			ambient_light: None,
+			focus: None,
+			paused_time: None,
+		}
+	}
+
+	fn is_fading(&self) -> bool {
+		self.dim_fade_step.is_some()
+	}
+
+	fn dim_target(&self, args: &Args) -> f32 {
+		let dimmed = self.dimmed
+			|| (self.idle && !self.idle_inhibited)
+			|| (self.locked && args.while_locked == session::LockBehavior::Dim);
+		if dimmed {
+			1.0
+		} else {
+			0.0
+		}
+	}
+
+	/// Moves `dim_level` towards its target, taking `--dim-fade` seconds for the full range.
+	/// There is no point in fading while the session is locked, so then the target is reached immediately.
+	#[allow(clippy::float_cmp)] // Exact, since the level is clamped to the target.
+	fn advance_dim_fade(&mut self, args: &Args) {
+		let target = self.dim_target(args);
+		let now = Instant::now();
+		let step = if args.dim_fade > 0.0 && !self.locked {
+			self
+				.dim_fade_step
+				.map_or(0.0, |last| (now - last).as_secs_f32() / args.dim_fade)
+		} else {
+			1.0
 		};
-		if event_send.send(event).is_err() {
-			break;
+		self.dim_level = if target > self.dim_level {
+			(self.dim_level + step).min(target)
+		} else {
+			(self.dim_level - step).max(target)
+		};
+		self.dim_fade_step = (self.dim_level != target).then_some(now);
+	}
+
+	fn handle_command(&mut self, command: control::Command) {
+		match command {
+			control::Command::Dim(switch) => switch.apply(&mut self.dimmed),
+			control::Command::Invert(switch) => switch.apply(&mut self.filters.invert),
+			control::Command::ReduceColor(switch) => switch.apply(&mut self.filters.reduce_color),
+			control::Command::Pause(switch) => switch.apply(&mut self.paused),
+			control::Command::Tint(tint) => self.tint = tint,
+		}
+	}
+
+	/// Applies the new lock state. On unlock the config is applied immediately, without fading.
+	fn set_locked(&mut self, args: &Args, locked: bool) {
+		let unlocked = self.locked && !locked;
+		self.locked = locked;
+		if unlocked {
+			self.dim_level = self.dim_target(args);
+			self.dim_fade_step = None;
+		}
+	}
+
+	fn config(&mut self, args: &Args, mut time: Time) -> Config {
+		let action = inhibit::action(&args.inhibit, self.focus.as_ref());
+		if self.paused || action == Some(inhibit::Action::Pause) {
+			time = *self.paused_time.get_or_insert(time);
+		} else {
+			self.paused_time = None;
+		}
+		let mut config = get_config(args, time, self.dim_level).with_filters(self.filters);
+		if let Some(tint) = self.tint {
+			config = config.with_tint(Some(tint));
+		}
+		if action == Some(inhibit::Action::Neutral)
+			|| (self.locked && args.while_locked == session::LockBehavior::Neutral)
+		{
+			config = config.with_tint(Some(Tint::NEUTRAL));
+		}
+		if let Some(level) = self.ambient_light {
+			config = ambient::adjust(config, level, &args.ambient);
+		}
+		config
+	}
+
+	/// `next_transition` is in local time.
+	fn status(&self, time: Time, config: Config, next_transition: Time) -> status::Status {
+		let class = if self.dim_level > 0.0 {
+			status::Class::Dimmed
+		} else if self.tint.is_some() {
+			status::Class::Override
+		} else if self.paused_time.is_some() {
+			status::Class::Paused
+		} else {
+			match schedule_position(time) {
+				(Some(_), _) => status::Class::Transition,
+				(None, true) => status::Class::Day,
+				(None, false) => status::Class::Night,
+			}
+		};
+		status::Status {
+			temperature: config.temperature(),
+			brightness: (config.brightness() * 100.0).round() as u32,
+			class,
+			next_transition,
+			tint: self.tint,
 		}
 	}
 }
 
-fn get_config(time: Time, dimmed: bool) -> Config {
-	let day_temp = 6500;
-	let night_temp = 3500;
-
-	let temperature = {
-		let daytime_start = Time::from_hms(7, 45, 0).unwrap();
-		let daytime_end = Time::from_hms(19, 45, 0).unwrap();
-		let fade_time = 30.minutes();
-
-		let daytime_diff = time - daytime_start;
-		let nighttime_diff = time - daytime_end;
-		if daytime_diff > Duration::ZERO && daytime_diff < fade_time {
-			lerp(
-				night_temp as f32,
-				day_temp as f32,
-				daytime_diff.as_seconds_f32() / fade_time.as_seconds_f32(),
-			) as u32
-		} else if nighttime_diff > Duration::ZERO && nighttime_diff < fade_time {
-			lerp(
-				day_temp as f32,
-				night_temp as f32,
-				nighttime_diff.as_seconds_f32() / fade_time.as_seconds_f32(),
-			) as u32
-		} else if time >= daytime_start && time <= daytime_end {
-			day_temp
+/// Creates the event loop with all event sources.
+///
+/// D-Bus signals are received on another thread, which is spawned here.
+fn create_event_loop(
+	args: &Args,
+	connection: Option<&Connection>,
+	dbus_time: Option<&dbus_time::DbusTime>,
+	mut event_loop: EventLoop,
+) -> Result<EventLoop, Error> {
+	let event_send = event_loop.sender();
+
+	if let Some(idle_timeout) = args.idle_timeout {
+		let idle_timeout = std::time::Duration::from_secs(idle_timeout);
+		if let Some(connection) = connection {
+			if let Some(source) = wayland::monitor_idle(event_send.clone(), connection, idle_timeout)? {
+				event_loop.add_wayland_source(source);
+			}
 		} else {
-			night_temp
+			tracing::warn!("--idle-timeout needs a Wayland compositor, not monitoring idle state");
 		}
-	};
+	}
+	if !args.inhibit.is_empty() {
+		if let Some(connection) = connection {
+			if let Some(source) = wayland::monitor_toplevels(event_send.clone(), connection)? {
+				event_loop.add_wayland_source(source);
+			}
+		} else {
+			tracing::warn!("--inhibit needs a Wayland compositor, inhibit rules will not apply");
+		}
+	}
+	// A dry run must not take over the control socket, which may belong to a running daemon.
+	if args.backend != backend::BackendKind::DryRun {
+		if let Some(listener) = control::Listener::bind() {
+			event_loop.set_control(listener);
+		}
+	}
+	if args.ambient.enabled {
+		if let Some(monitor) = ambient::Monitor::new(&args.ambient) {
+			event_loop.set_ambient(monitor);
+		}
+	}
 
-	let brightness = if dimmed { 0.4 } else { 1.0 };
+	if let Some(dbus_time) = dbus_time {
+		spawn_dbus_monitor(
+			dbus_time.connection().inner().clone(),
+			args.idle_timeout.is_some(),
+			event_send,
+		);
+	}
 
-	Config::new(temperature, brightness).unwrap()
+	Ok(event_loop)
 }
 
-fn main() {
-	tracing_subscriber::fmt::init();
+/// Forwards the D-Bus signals that the daemon reacts to as events.
+///
+/// zbus does not give access to its socket, so D-Bus cannot be part of the event loop.
+/// Instead, all signals are received on this one thread, over the connection that is also used for the time zone.
+fn spawn_dbus_monitor(dbus: zbus::Connection, idle_inhibitors: bool, event_send: EventSender) {
+	std::thread::spawn(move || {
+		futures_lite::future::block_on(async {
+			let sources = [
+				(
+					"the session lock state",
+					Some(session::lock_events(&dbus).await),
+				),
+				(
+					"idle inhibitors",
+					if idle_inhibitors {
+						Some(session::idle_inhibitor_events(&dbus).await)
+					} else {
+						None
+					},
+				),
+				(
+					"time zone changes",
+					Some(dbus_time::DbusTime::timezone_events(&dbus).await),
+				),
+			];
+			let mut events = futures_lite::stream::empty().boxed();
+			for (name, source) in sources {
+				match source {
+					Some(Ok(source)) => events = events.race(source).boxed(),
+					Some(Err(error)) => tracing::warn!(%error, "not monitoring {name}"),
+					None => {}
+				}
+			}
+			while let Some(event) = events.next().await {
+				if event_send.send(event).is_err() {
+					break;
+				}
+			}
+		});
+	});
+}
 
-	let dbus_time = crate::dbus_time::DbusTime::connect();
+/// Loads the `--icc-profile` and `--base-curve` calibrations, keyed by output.
+fn load_calibrations(args: &Args) -> Result<Vec<(&str, Curves)>, Error> {
+	args
+		.icc_profiles
+		.iter()
+		.map(|(output, path)| (output, icc::load_vcgt(path)))
+		.chain(
+			args
+				.base_curves
+				.iter()
+				.map(|(output, path)| (output, ramp_file::load_curves(path))),
+		)
+		.map(|(output, curves)| Ok((output.as_str(), curves.map_err(Error::Config)?)))
+		.collect()
+}
 
-	// Application state
-	let mut dimmed = false;
-	let mut gamma_controls = Vec::new();
+/// Returns `None` if there is no Wayland compositor, but the backend does not need one, or for dry runs.
+fn connect_wayland(args: &Args) -> Result<Option<Connection>, Error> {
+	if args.backend == backend::BackendKind::DryRun {
+		return Ok(None);
+	}
+	match Connection::connect_to_env() {
+		Ok(connection) => Ok(Some(connection)),
+		Err(error) if args.backend.needs_wayland() => Err(Error::WaylandConnect(error)),
+		Err(error) => {
+			tracing::debug!(%error, "not connected to a Wayland compositor");
+			Ok(None)
+		}
+	}
+}
 
-	let connection = Connection::connect_to_env().expect("connecting to wayland from env");
+fn run_daemon(args: &Args) -> Result<(), Error> {
+	let calibrations = load_calibrations(args)?;
 
-	// Event sources
-	let (event_send, event_recv) = std::sync::mpsc::sync_channel::<Event>(4);
+	let connection = connect_wayland(args)?;
+	// This has to happen before any threads are spawned, including by zbus.
+	let event_loop = EventLoop::new(connection.clone())?;
 
-	std::thread::spawn({
-		let event_send = event_send.clone();
-		let connection = connection.clone();
-		move || wayland::monitor_outputs(event_send, &connection)
-	});
-	std::thread::spawn({
-		let event_send = event_send.clone();
-		move || update_regularly(&event_send)
-	});
-	std::thread::spawn({
-		let event_send = event_send.clone();
-		move || signal_handler(&event_send)
-	});
-	std::thread::spawn({
-		let event_send = event_send.clone();
-		let dbus_time = dbus_time.clone();
-		move || dbus_time.handle_timezone_updates(&event_send)
-	});
+	let dbus_time = match dbus_time::DbusTime::connect() {
+		Ok(dbus_time) => Some(dbus_time),
+		// Dry runs should also work where there is no system bus, e.g., in containers.
+		Err(error) if args.backend == backend::BackendKind::DryRun => {
+			tracing::warn!(%error, "using the time zone from TZ or /etc/localtime");
+			None
+		}
+		Err(error) => return Err(error),
+	};
+	let clock: &dyn clock::Clock = match &dbus_time {
+		Some(dbus_time) => dbus_time,
+		None => &clock::LocalClock,
+	};
 
+	let backend = backend::open(args.backend, event_loop.sender(), connection.as_ref())?;
+	let mut event_loop =
+		create_event_loop(args, connection.as_ref(), dbus_time.as_ref(), event_loop)?;
+
+	// A dry run must not change anything.
+	let backlight = if args.backlight.enabled && args.backend != backend::BackendKind::DryRun {
+		let backlight = backlight::Backlight::open(&args.backlight)?;
+		if backlight.is_none() {
+			tracing::warn!(root = ?args.backlight.backlight_root, "no usable backlight found");
+		}
+		backlight
+	} else {
+		None
+	};
+
+	let mut daemon = Daemon::new(args, clock, calibrations, backend, backlight);
 	// Main loop
-	let mut ignored_queue = connection.new_event_queue();
-	while let Ok(event) = event_recv.recv() {
+	loop {
+		let timeout = daemon.timeout();
+		let event = event_loop.next(&mut *daemon.backend, timeout)?;
+		if daemon.handle_event(event)?.is_break() {
+			break;
+		}
+	}
+
+	// The backlight is restored when it is dropped.
+	// The gamma is restored when the backend disconnects.
+	Ok(())
+}
+
+/// The state of the running daemon, which is updated by the events from the event loop.
+struct Daemon<'a> {
+	args: &'a Args,
+	clock: &'a dyn clock::Clock,
+	calibrations: Vec<(&'a str, Curves)>,
+	state: State,
+	outputs: Vec<backend::Output>,
+	backend: Box<dyn backend::GammaBackend>,
+	backlight: Option<backlight::Backlight>,
+	notifier: notify::Notifier,
+	outputs_enumerated: bool,
+	watchers: Vec<UnixStream>,
+	last_status: Option<status::Status>,
+	/// The schedule time and config of the last update.
+	last_update: Option<(Time, Config)>,
+}
+
+impl<'a> Daemon<'a> {
+	fn new(
+		args: &'a Args,
+		clock: &'a dyn clock::Clock,
+		calibrations: Vec<(&'a str, Curves)>,
+		backend: Box<dyn backend::GammaBackend>,
+		backlight: Option<backlight::Backlight>,
+	) -> Self {
+		Self {
+			args,
+			clock,
+			calibrations,
+			state: State::new(args),
+			outputs: Vec::new(),
+			backend,
+			backlight,
+			notifier: notify::Notifier::from_env(),
+			outputs_enumerated: false,
+			watchers: Vec::new(),
+			last_status: None,
+			last_update: None,
+		}
+	}
+
+	/// How long the event loop may wait for an event before the config has to be updated.
+	fn timeout(&self) -> Option<std::time::Duration> {
+		let fade_timeout = self.state.is_fading().then_some(FADE_INTERVAL);
+		[
+			fade_timeout,
+			self.notifier.watchdog_due_in(),
+			self.backend.update_interval(),
+			self.clock.update_interval(),
+		]
+		.into_iter()
+		.flatten()
+		.min()
+	}
+
+	/// Handles the event and then updates the config, or breaks on `Event::Quit`.
+	fn handle_event(&mut self, event: Event) -> Result<ControlFlow<()>, Error> {
 		tracing::debug!(?event, "got event");
+		let state = &mut self.state;
 		match event {
-			Event::AddOutput(output) => gamma_controls.push(output),
-			Event::RemoveOutput {
-				output_registry_name: output_id,
-			} => {
-				gamma_controls.retain(|control| !control.is_for_output(output_id));
+			Event::AddOutput(info) => {
+				let mut output = backend::Output::new(info);
+				let curves = self
+					.calibrations
+					.iter()
+					.find(|(key, _curves)| output.matches_output(key))
+					.map(|(_key, curves)| curves.clone());
+				output.set_curves(curves);
+				self.outputs.push(output);
+			}
+			Event::RemoveOutput { id } => {
+				self.outputs.retain(|output| output.id() != id);
 				// No need to update the other outputs.
-				continue;
+				return Ok(ControlFlow::Continue(()));
 			}
+			Event::OutputsEnumerated => self.outputs_enumerated = true,
 			Event::Update => {}
 			Event::SetDimmed(new) => {
-				dimmed = new;
+				state.dimmed = new;
+			}
+			Event::SetIdle(new) => {
+				state.idle = new;
+			}
+			Event::SetIdleInhibited(new) => {
+				state.idle_inhibited = new;
+			}
+			Event::SetLocked(new) => state.set_locked(self.args, new),
+			Event::Control(command) => state.handle_command(command),
+			Event::SetAmbientLight(level) => state.ambient_light = Some(level),
+			Event::SetFocus(focus) => state.focus = focus,
+			Event::Watch(stream) => {
+				self.watchers.push(stream);
+				self.last_status = None;
+			}
+			Event::Fatal(error) => return Err(error),
+			Event::Quit => return Ok(ControlFlow::Break(())),
+		}
+		self.update()?;
+		Ok(ControlFlow::Continue(()))
+	}
+
+	fn update(&mut self) -> Result<(), Error> {
+		let args = self.args;
+		self.state.advance_dim_fade(args);
+		let (time_zone, now) = self.clock.schedule_now()?;
+		let time = now.time();
+		let mut config = self.state.config(args, time);
+		let (_previous_transition, next_transition) = surrounding_transitions(&time_zone, now)?;
+		let status = self.state.status(time, config, next_transition.time());
+		if let Some(backlight) = &mut self.backlight {
+			config = config.with_brightness(backlight.apply(config.brightness()));
+		}
+		self.backend.set_config(time, config)?;
+		for output in &mut self.outputs {
+			output.set_gamma(&mut *self.backend, config)?;
+		}
+		self.backend.flush()?;
+		self.last_update = Some((time, config));
+
+		// Flushing shows that the connection to the display stack is still alive.
+		self.notifier.watchdog();
+		if self.outputs_enumerated {
+			self.notifier.ready();
+		}
+		self.notifier.status(format!(
+			"{}K, {:.0}% brightness",
+			config.temperature(),
+			config.brightness() * 100.0
+		));
+		if self.last_status != Some(status) {
+			self.last_status = Some(status);
+			// Drop watchers that disconnected.
+			self
+				.watchers
+				.retain_mut(|watcher| writeln!(watcher, "{status}").is_ok());
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	use clap::Parser as _;
+	use time::macros::{datetime, time};
+
+	use super::{get_config, Daemon};
+	use crate::cli::Args;
+	use crate::clock::SimulatedClock;
+	use crate::color::Ramps;
+	use crate::event_loop::EventLoop;
+	use crate::test_compositor::{OutputConfig, TestCompositor, TestOutputId};
+	use crate::{dry_run, wlr, Event};
+
+	/// The gamma that the daemon should set at the schedule time.
+	fn expected_gamma(args: &Args, time: time::Time) -> Vec<u8> {
+		let mut ramps = Ramps::new(256);
+		get_config(args, time, 0.0).generate_ramps(&mut ramps, None);
+		ramps.as_bytes().to_vec()
+	}
+
+	/// Runs the daemon against the test compositor at simulated times.
+	struct Harness {
+		compositor: TestCompositor,
+		output: TestOutputId,
+		args: Args,
+		clock: SimulatedClock,
+	}
+
+	impl Harness {
+		fn new(start: time::OffsetDateTime, time_zone: &str) -> Self {
+			let compositor = TestCompositor::start();
+			let output = compositor.add_output(OutputConfig::new("DP-1"));
+			Self {
+				compositor,
+				output,
+				args: Args::parse_from(["rustshift"]),
+				clock: SimulatedClock::new(start, time_zone),
+			}
+		}
+
+		fn daemon(&self, event_loop: &EventLoop) -> Daemon<'_> {
+			let connection = self.compositor.connect();
+			let backend = wlr::Backend::new(event_loop.sender(), &connection).unwrap();
+			Daemon::new(&self.args, &self.clock, Vec::new(), Box::new(backend), None)
+		}
+
+		/// Handles the pending events and an update, and returns the last gamma that the compositor received.
+		fn update(&self, event_loop: &mut EventLoop, daemon: &mut Daemon<'_>) -> Vec<u8> {
+			loop {
+				// Without a timeout, the loop only returns events that were already received.
+				let event = event_loop
+					.next(&mut *daemon.backend, Some(std::time::Duration::ZERO))
+					.unwrap();
+				let update = matches!(event, Event::Update);
+				assert!(daemon.handle_event(event).unwrap().is_continue());
+				if update {
+					break;
+				}
 			}
+			daemon.backend.flush().unwrap();
+			self
+				.compositor
+				.received_gamma(self.output)
+				.pop()
+				.expect("no gamma was set")
+		}
+	}
+
+	#[test]
+	fn follows_schedule() {
+		// The schedule time is an hour ahead of UTC, since daylight savings time is cancelled out.
+		let harness = Harness::new(datetime!(2026-06-01 10:00 UTC), "UTC");
+		let mut event_loop = EventLoop::new(None).unwrap();
+		let mut daemon = harness.daemon(&event_loop);
+		let day = expected_gamma(&harness.args, time!(11:00));
+		assert_eq!(harness.update(&mut event_loop, &mut daemon), day);
+
+		// Halfway through the evening transition.
+		harness.clock.advance(time::Duration::hours(9));
+		let transition = expected_gamma(&harness.args, time!(20:00));
+		assert_ne!(transition, day);
+		assert_eq!(harness.update(&mut event_loop, &mut daemon), transition);
+
+		harness.clock.advance(time::Duration::hours(2));
+		let night = expected_gamma(&harness.args, time!(22:00));
+		assert_ne!(night, transition);
+		assert_eq!(harness.update(&mut event_loop, &mut daemon), night);
+	}
+
+	/// Collects what the dry-run backend prints.
+	#[derive(Clone, Default)]
+	struct Printed(Rc<RefCell<Vec<u8>>>);
+
+	impl std::io::Write for Printed {
+		fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+			self.0.borrow_mut().write(buffer)
 		}
-		let config = get_config(dbus_time.get_time(), dimmed);
-		for control in &mut gamma_controls {
-			control.set_gamma(config);
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
 		}
-		ignored_queue.roundtrip(&mut Ignored).unwrap();
 	}
 
-	// When a gamma control object is destroyed, its gamma table is restored.
+	#[test]
+	fn dry_run() {
+		let args = Args::parse_from(["rustshift", "--backend", "dry-run"]);
+		let clock = SimulatedClock::new(datetime!(2026-06-01 19:00 UTC), "UTC");
+		let mut event_loop = EventLoop::new(None).unwrap();
+		let printed = Printed::default();
+		let backend = dry_run::Backend::with_output(&event_loop.sender(), Box::new(printed.clone()));
+		let mut daemon = Daemon::new(&args, &clock, Vec::new(), Box::new(backend), None);
+		// Adding the output and enumerating the outputs also update.
+		loop {
+			let event = event_loop
+				.next(&mut *daemon.backend, Some(std::time::Duration::ZERO))
+				.unwrap();
+			let update = matches!(event, Event::Update);
+			assert!(daemon.handle_event(event).unwrap().is_continue());
+			if update {
+				break;
+			}
+		}
+
+		// Halfway through the evening transition, since the schedule time is an hour ahead of UTC.
+		printed.0.borrow_mut().clear();
+		assert!(daemon.handle_event(Event::Update).unwrap().is_continue());
+		assert_eq!(
+			String::from_utf8_lossy(&printed.0.borrow()),
+			"20:00 Config { temperature: 5000, brightness: 1.0, white_point_model: Table, tint: None, \
+			 filters: Filters { invert: false, reduce_color: false } }\n  \
+			 dry-run: ramps 5f37c4e4be068474\n"
+		);
+	}
+
+	#[test]
+	fn dst_change() {
+		// Summer time starts in Central Europe in between, which must not shift the schedule.
+		let harness = Harness::new(
+			datetime!(2026-03-28 06:00 UTC),
+			"CET-1CEST,M3.5.0,M10.5.0/3",
+		);
+		let mut event_loop = EventLoop::new(None).unwrap();
+		let mut daemon = harness.daemon(&event_loop);
+		let transition = expected_gamma(&harness.args, time!(08:00));
+		assert_eq!(harness.update(&mut event_loop, &mut daemon), transition);
+
+		harness.clock.advance(time::Duration::DAY);
+		assert_eq!(harness.update(&mut event_loop, &mut daemon), transition);
+	}
+
+	#[test]
+	fn time_zone_change() {
+		let harness = Harness::new(datetime!(2026-06-01 12:00 UTC), "UTC");
+		let mut event_loop = EventLoop::new(None).unwrap();
+		let mut daemon = harness.daemon(&event_loop);
+		assert_eq!(
+			harness.update(&mut event_loop, &mut daemon),
+			expected_gamma(&harness.args, time!(13:00))
+		);
+
+		// Timedated sends an update when the time zone changes.
+		harness.clock.set_time_zone("JST-9");
+		event_loop.sender().send(Event::Update).unwrap();
+		assert_eq!(
+			harness.update(&mut event_loop, &mut daemon),
+			expected_gamma(&harness.args, time!(22:00))
+		);
+	}
+
+	#[test]
+	fn next_transition_in_local_time() {
+		// Outside of summer time, the schedule runs an hour ahead of the wall clock.
+		for (start, next_transition) in [
+			(datetime!(2026-12-21 12:00 UTC), time!(18:45)),
+			(datetime!(2026-06-01 12:00 UTC), time!(19:45)),
+			(datetime!(2026-06-01 22:00 UTC), time!(07:45)),
+		] {
+			let harness = Harness::new(start, "CET-1CEST,M3.5.0,M10.5.0/3");
+			let mut event_loop = EventLoop::new(None).unwrap();
+			let mut daemon = harness.daemon(&event_loop);
+			harness.update(&mut event_loop, &mut daemon);
+			assert_eq!(
+				daemon.last_status.unwrap().next_transition,
+				next_transition,
+				"{start}"
+			);
+		}
+	}
 }
@@ -0,0 +1,552 @@
+//! The `gnome` and `kde` backends, which delegate to the desktops' own night light instead of setting the gamma.
+//!
+//! GNOME and KDE do not let other programs set the gamma, but their night lights can be driven to follow our schedule.
+//! Only the temperature is delegated, so dimming and filters have no effect with these backends.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use zbus::dbus_proxy;
+
+use crate::backend::{GammaBackend, OutputId};
+use crate::color::{Config, Ramps};
+use crate::dconf::{self, Dconf};
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::Event;
+
+#[dbus_proxy(
+	interface = "org.gnome.SettingsDaemon.Color",
+	default_service = "org.gnome.SettingsDaemon.Color",
+	default_path = "/org/gnome/SettingsDaemon/Color",
+	gen_async = false
+)]
+trait GnomeColor {
+	#[dbus_proxy(property)]
+	fn night_light_active(&self) -> zbus::Result<bool>;
+}
+
+#[dbus_proxy(
+	interface = "org.kde.KWin.NightLight",
+	default_service = "org.kde.KWin",
+	default_path = "/org/kde/KWin/NightLight",
+	gen_async = false
+)]
+trait KdeNightLight {
+	/// Shows the temperature until `stop_preview` is called, or for 15 seconds.
+	// KWin's members are in camel case, not in the Pascal case that zbus uses by default.
+	#[dbus_proxy(name = "preview")]
+	fn preview(&self, temperature: u32) -> zbus::Result<()>;
+
+	#[dbus_proxy(name = "stopPreview")]
+	fn stop_preview(&self) -> zbus::Result<()>;
+
+	#[dbus_proxy(property, name = "available")]
+	fn available(&self) -> zbus::Result<bool>;
+}
+
+/// Where `org.gnome.settings-daemon.plugins.color` is stored in dconf.
+const GNOME_SETTINGS_PATH: &str = "/org/gnome/settings-daemon/plugins/color/";
+/// The settings that we change, which are restored on exit.
+const GNOME_KEYS: [&str; 5] = [
+	"night-light-enabled",
+	"night-light-schedule-automatic",
+	"night-light-schedule-from",
+	"night-light-schedule-to",
+	"night-light-temperature",
+];
+/// The range of `night-light-temperature` in the schema.
+const GNOME_TEMPERATURE_RANGE: (u32, u32) = (1700, 4700);
+/// Temperatures at or above this disable the night light, and ones below are rounded to at most 4700K.
+const GNOME_DISABLE_TEMPERATURE: u32 = 5600;
+
+/// Drives GNOME's night light through its settings in dconf, which `gsd-color` follows.
+///
+/// The night light is switched to a schedule that is always active, and its temperature is set to ours.
+/// The user's settings are restored on exit. Until then, they are also kept in a file,
+/// from which they are recovered on the next start if we did not exit cleanly.
+pub struct Gnome {
+	dconf: Dconf,
+	/// The settings from before we changed them, with `None` for the ones that were not set.
+	original_settings: Vec<(&'static str, Option<dconf::Value>)>,
+	/// Where `original_settings` are saved, if anywhere.
+	state_file: Option<PathBuf>,
+	/// What was last set, if anything.
+	setting: Option<GnomeSetting>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GnomeSetting {
+	Disabled,
+	Temperature(u32),
+}
+
+impl Gnome {
+	pub fn new(event_send: &EventSender) -> Result<Self, Error> {
+		let dbus = zbus::blocking::Connection::session()
+			.map_err(Error::dbus("could not connect to the D-Bus session bus"))?;
+		let state_file = std::env::var_os("XDG_STATE_HOME")
+			.map(PathBuf::from)
+			.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+			.map(|state_dir| state_dir.join("rustshift/gnome-night-light"));
+		Self::connect(&dbus, Dconf::new(&dbus)?, state_file, event_send)
+	}
+
+	fn connect(
+		dbus: &zbus::blocking::Connection,
+		dconf: Dconf,
+		state_file: Option<PathBuf>,
+		event_send: &EventSender,
+	) -> Result<Self, Error> {
+		GnomeColorProxy::new(dbus)
+			.and_then(|proxy| proxy.night_light_active())
+			.map_err(Error::dbus("GNOME's color plugin is not running"))?;
+
+		let saved_settings = state_file.as_deref().and_then(|path| {
+			let settings = std::fs::read_to_string(path).ok()?;
+			parse_gnome_settings(&settings)
+				.inspect_err(|error| tracing::warn!(?path, %error, "ignoring saved night light settings"))
+				.ok()
+		});
+		let original_settings = if let Some(settings) = saved_settings {
+			// The settings in dconf are still ours.
+			tracing::info!(
+				"using the night light settings saved by a previous run that did not exit cleanly"
+			);
+			settings
+		} else {
+			let settings: Vec<_> = GNOME_KEYS
+				.into_iter()
+				.zip(dconf.read(GNOME_KEYS.map(gnome_key))?)
+				.collect();
+			if let Some(path) = &state_file {
+				if let Err(error) = save_gnome_settings(path, &settings) {
+					tracing::warn!(?path, %error, "could not save the night light settings");
+				}
+			}
+			settings
+		};
+		let gnome = Self {
+			dconf,
+			original_settings,
+			state_file,
+			setting: None,
+		};
+		// The schedule is always active if it starts and ends at the same time.
+		gnome.write(&[
+			(
+				"night-light-schedule-automatic",
+				Some(dconf::Value::Bool(false)),
+			),
+			("night-light-schedule-from", Some(dconf::Value::F64(0.0))),
+			("night-light-schedule-to", Some(dconf::Value::F64(0.0))),
+		])?;
+
+		// There are no outputs to report.
+		_ = event_send.send(Event::OutputsEnumerated);
+		Ok(gnome)
+	}
+
+	fn write(&self, settings: &[(&str, Option<dconf::Value>)]) -> Result<(), Error> {
+		self
+			.dconf
+			.write(settings.iter().map(|&(key, value)| (gnome_key(key), value)))
+	}
+}
+
+fn gnome_key(key: &str) -> String {
+	format!("{GNOME_SETTINGS_PATH}{key}")
+}
+
+/// One `KEY=VALUE` line per setting, with the value in `gsettings` syntax, or empty if it was not set.
+fn save_gnome_settings(
+	path: &Path,
+	settings: &[(&'static str, Option<dconf::Value>)],
+) -> std::io::Result<()> {
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+	let mut contents = String::new();
+	for (key, value) in settings {
+		let value = value.map(|value| value.to_string()).unwrap_or_default();
+		_ = writeln!(contents, "{key}={value}");
+	}
+	std::fs::write(path, contents)
+}
+
+fn parse_gnome_settings(
+	contents: &str,
+) -> Result<Vec<(&'static str, Option<dconf::Value>)>, String> {
+	GNOME_KEYS
+		.into_iter()
+		.map(|key| {
+			let value = contents
+				.lines()
+				.find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+				.ok_or_else(|| format!("missing {key}"))?;
+			let value = (!value.is_empty()).then(|| value.parse()).transpose()?;
+			Ok((key, value))
+		})
+		.collect()
+}
+
+impl GammaBackend for Gnome {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_gamma(&mut self, _output: OutputId, _ramps: &Ramps) -> Result<(), Error> {
+		Ok(())
+	}
+
+	/// The night light only goes up to 4700K, so anything above that is rounded to either 4700K or disabled.
+	fn set_config(&mut self, _time: time::Time, config: Config) -> Result<(), Error> {
+		let temperature = config.temperature();
+		let (min, max) = GNOME_TEMPERATURE_RANGE;
+		let setting = if temperature < GNOME_DISABLE_TEMPERATURE {
+			GnomeSetting::Temperature(temperature.clamp(min, max))
+		} else {
+			GnomeSetting::Disabled
+		};
+		if self.setting == Some(setting) {
+			return Ok(());
+		}
+		self.setting = Some(setting);
+		match setting {
+			GnomeSetting::Disabled => {
+				self.write(&[("night-light-enabled", Some(dconf::Value::Bool(false)))])
+			}
+			GnomeSetting::Temperature(temperature) => self.write(&[
+				(
+					"night-light-temperature",
+					Some(dconf::Value::U32(temperature)),
+				),
+				("night-light-enabled", Some(dconf::Value::Bool(true))),
+			]),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+impl Drop for Gnome {
+	fn drop(&mut self) {
+		if let Err(error) = self.write(&self.original_settings) {
+			tracing::warn!(%error, "could not restore the night light settings");
+			return;
+		}
+		if let Some(path) = &self.state_file {
+			_ = std::fs::remove_file(path);
+		}
+	}
+}
+
+/// KDE ends previews after 15 seconds, so they are renewed more often than that.
+const KDE_PREVIEW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Drives KDE's night light by previewing our temperature, which overrides its own schedule while the preview lasts.
+pub struct Kde {
+	proxy: KdeNightLightProxy<'static>,
+}
+
+impl Kde {
+	pub fn new(event_send: &EventSender) -> Result<Self, Error> {
+		let dbus = zbus::blocking::Connection::session()
+			.map_err(Error::dbus("could not connect to the D-Bus session bus"))?;
+		Self::connect(&dbus, event_send)
+	}
+
+	fn connect(dbus: &zbus::blocking::Connection, event_send: &EventSender) -> Result<Self, Error> {
+		let proxy = KdeNightLightProxy::new(dbus)
+			.map_err(Error::dbus("could not create a KWin night light proxy"))?;
+		let available = proxy
+			.available()
+			.map_err(Error::dbus("KWin's night light is not running"))?;
+		if !available {
+			return Err(Error::MissingProtocol("KWin night light"));
+		}
+
+		// There are no outputs to report.
+		_ = event_send.send(Event::OutputsEnumerated);
+		Ok(Self { proxy })
+	}
+}
+
+impl GammaBackend for Kde {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_gamma(&mut self, _output: OutputId, _ramps: &Ramps) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn set_config(&mut self, _time: time::Time, config: Config) -> Result<(), Error> {
+		self
+			.proxy
+			.preview(config.temperature())
+			.map_err(Error::dbus("could not preview the night light temperature"))
+	}
+
+	fn update_interval(&self) -> Option<Duration> {
+		Some(KDE_PREVIEW_INTERVAL)
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+impl Drop for Kde {
+	fn drop(&mut self) {
+		if let Err(error) = self.proxy.stop_preview() {
+			tracing::warn!(%error, "could not stop the night light preview");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use std::path::Path;
+	use std::sync::{Arc, Mutex};
+
+	use byteorder::NativeEndian;
+	use time::macros::time;
+	use zbus::dbus_interface;
+	use zbus::zvariant::{self, EncodingContext, OwnedValue};
+
+	use super::{gnome_key, Gnome, Kde, GNOME_SETTINGS_PATH};
+	use crate::backend::GammaBackend as _;
+	use crate::color::Config;
+	use crate::dconf::{self, Dconf, Value};
+	use crate::event_loop::EventSender;
+	use crate::test_dbus::connect;
+
+	type Changes = Arc<Mutex<Vec<HashMap<String, Option<Value>>>>>;
+
+	/// Records the changes instead of writing them.
+	struct FakeDconf {
+		changes: Changes,
+	}
+
+	#[dbus_interface(name = "ca.desrt.dconf.Writer")]
+	impl FakeDconf {
+		fn change(&self, changes: &[u8]) -> String {
+			let ctxt = EncodingContext::<NativeEndian>::new_gvariant(0);
+			let changes: HashMap<String, Option<OwnedValue>> =
+				zvariant::from_slice(changes, ctxt).unwrap();
+			let changes = changes
+				.into_iter()
+				.map(|(key, value)| {
+					let key = key.strip_prefix(GNOME_SETTINGS_PATH).unwrap().to_owned();
+					let value = value.map(|value| match &*value {
+						zvariant::Value::Bool(value) => Value::Bool(*value),
+						zvariant::Value::U32(value) => Value::U32(*value),
+						zvariant::Value::F64(value) => Value::F64(*value),
+						value => panic!("unexpected value {value:?}"),
+					});
+					(key, value)
+				})
+				.collect();
+			self.changes.lock().unwrap().push(changes);
+			"tag".into()
+		}
+	}
+
+	struct FakeColor;
+
+	#[dbus_interface(name = "org.gnome.SettingsDaemon.Color")]
+	impl FakeColor {
+		#[allow(clippy::unused_self)]
+		#[dbus_interface(property)]
+		fn night_light_active(&self) -> bool {
+			false
+		}
+	}
+
+	/// Records the calls, by their member names on the bus.
+	struct FakeKwin {
+		calls: Calls,
+	}
+
+	type Calls = Arc<Mutex<Vec<String>>>;
+
+	#[dbus_interface(name = "org.kde.KWin.NightLight")]
+	impl FakeKwin {
+		#[dbus_interface(name = "preview")]
+		fn preview(&self, temperature: u32) {
+			self
+				.calls
+				.lock()
+				.unwrap()
+				.push(format!("preview {temperature}"));
+		}
+
+		#[dbus_interface(name = "stopPreview")]
+		fn stop_preview(&self) {
+			self.calls.lock().unwrap().push("stopPreview".into());
+		}
+
+		#[allow(clippy::unused_self)]
+		#[dbus_interface(property, name = "available")]
+		fn available(&self) -> bool {
+			true
+		}
+	}
+
+	fn start(
+		directory: &Path,
+		database: &[(&str, Value)],
+		changes: &Changes,
+	) -> (Gnome, [zbus::blocking::Connection; 2]) {
+		let changes = changes.clone();
+		let connection = connect(move |builder| {
+			builder
+				.serve_at("/ca/desrt/dconf/Writer/user", FakeDconf { changes })?
+				.serve_at("/org/gnome/SettingsDaemon/Color", FakeColor)
+		});
+		let database_path = directory.join("user");
+		let keys = database
+			.iter()
+			.map(|(key, _value)| gnome_key(key))
+			.collect::<Vec<_>>();
+		let database = keys
+			.iter()
+			.zip(database)
+			.map(|(key, (_key, value))| (key.as_str(), *value))
+			.collect::<Vec<_>>();
+		std::fs::write(&database_path, dconf::database(&database)).unwrap();
+		let dconf = Dconf::with_database(&connection[0], database_path).unwrap();
+		let (event_send, _event_recv) = EventSender::channel();
+		let gnome = Gnome::connect(
+			&connection[0],
+			dconf,
+			Some(directory.join("state/gnome-night-light")),
+			&event_send,
+		)
+		.unwrap();
+		(gnome, connection)
+	}
+
+	fn change(settings: &[(&str, Option<Value>)]) -> HashMap<String, Option<Value>> {
+		settings
+			.iter()
+			.map(|&(key, value)| (key.to_owned(), value))
+			.collect()
+	}
+
+	#[test]
+	fn changes_and_restores_settings() {
+		let directory = tempfile::tempdir().unwrap();
+		let state_file = directory.path().join("state/gnome-night-light");
+		let changes = Changes::default();
+		let (mut gnome, _connection) = start(
+			directory.path(),
+			&[
+				("night-light-enabled", Value::Bool(true)),
+				("night-light-schedule-from", Value::F64(20.5)),
+				("night-light-temperature", Value::U32(3500)),
+			],
+			&changes,
+		);
+		assert_eq!(
+			std::fs::read_to_string(&state_file).unwrap(),
+			"night-light-enabled=true\n\
+			 night-light-schedule-automatic=\n\
+			 night-light-schedule-from=20.5\n\
+			 night-light-schedule-to=\n\
+			 night-light-temperature=uint32 3500\n"
+		);
+
+		for temperature in [3000, 6500] {
+			let config = Config::new(temperature, 1.0).unwrap();
+			gnome.set_config(time!(12:00), config).unwrap();
+		}
+		drop(gnome);
+		assert_eq!(
+			*changes.lock().unwrap(),
+			[
+				change(&[
+					("night-light-schedule-automatic", Some(Value::Bool(false))),
+					("night-light-schedule-from", Some(Value::F64(0.0))),
+					("night-light-schedule-to", Some(Value::F64(0.0))),
+				]),
+				change(&[
+					("night-light-temperature", Some(Value::U32(3000))),
+					("night-light-enabled", Some(Value::Bool(true))),
+				]),
+				change(&[("night-light-enabled", Some(Value::Bool(false)))]),
+				change(&[
+					("night-light-enabled", Some(Value::Bool(true))),
+					("night-light-schedule-automatic", None),
+					("night-light-schedule-from", Some(Value::F64(20.5))),
+					("night-light-schedule-to", None),
+					("night-light-temperature", Some(Value::U32(3500))),
+				]),
+			]
+		);
+		assert!(!state_file.exists());
+	}
+
+	#[test]
+	fn recovers_settings_after_crash() {
+		let directory = tempfile::tempdir().unwrap();
+		let state_file = directory.path().join("state/gnome-night-light");
+		std::fs::create_dir(directory.path().join("state")).unwrap();
+		std::fs::write(
+			&state_file,
+			"night-light-enabled=false\n\
+			 night-light-schedule-automatic=true\n\
+			 night-light-schedule-from=21.0\n\
+			 night-light-schedule-to=7.0\n\
+			 night-light-temperature=\n",
+		)
+		.unwrap();
+		let changes = Changes::default();
+		// What the previous run left behind.
+		let (gnome, _connection) = start(
+			directory.path(),
+			&[
+				("night-light-enabled", Value::Bool(true)),
+				("night-light-schedule-automatic", Value::Bool(false)),
+				("night-light-schedule-from", Value::F64(0.0)),
+				("night-light-schedule-to", Value::F64(0.0)),
+				("night-light-temperature", Value::U32(2700)),
+			],
+			&changes,
+		);
+
+		drop(gnome);
+		assert_eq!(
+			changes.lock().unwrap().last().unwrap(),
+			&change(&[
+				("night-light-enabled", Some(Value::Bool(false))),
+				("night-light-schedule-automatic", Some(Value::Bool(true))),
+				("night-light-schedule-from", Some(Value::F64(21.0))),
+				("night-light-schedule-to", Some(Value::F64(7.0))),
+				("night-light-temperature", None),
+			])
+		);
+		assert!(!state_file.exists());
+	}
+
+	#[test]
+	fn previews_temperature_in_kde() {
+		let calls = Calls::default();
+		let connection = connect({
+			let calls = calls.clone();
+			move |builder| builder.serve_at("/org/kde/KWin/NightLight", FakeKwin { calls })
+		});
+		let (event_send, _event_recv) = EventSender::channel();
+		let mut kde = Kde::connect(&connection[0], &event_send).unwrap();
+		kde
+			.set_config(time!(22:00), Config::new(3400, 0.5).unwrap())
+			.unwrap();
+		drop(kde);
+		assert_eq!(*calls.lock().unwrap(), ["preview 3400", "stopPreview"]);
+	}
+}
@@ -0,0 +1,96 @@
+//! The systemd notification protocol (`sd_notify`), for readiness, status, and watchdog notifications.
+//!
+//! All notifications are no-ops if rustshift was not started by systemd with `NOTIFY_SOCKET` set.
+
+use std::os::linux::net::SocketAddrExt as _;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+pub struct Notifier {
+	socket: Option<(UnixDatagram, SocketAddr)>,
+	/// How often `WATCHDOG=1` must be sent, if the watchdog is enabled.
+	watchdog_interval: Option<Duration>,
+	last_watchdog: Instant,
+	ready: bool,
+	status: String,
+}
+
+impl Notifier {
+	pub fn from_env() -> Self {
+		let socket = std::env::var_os("NOTIFY_SOCKET").and_then(|path| {
+			let address = match path.as_encoded_bytes().strip_prefix(b"@") {
+				Some(name) => SocketAddr::from_abstract_name(name),
+				None => SocketAddr::from_pathname(&path),
+			};
+			let socket = address
+				.and_then(|address| Ok((UnixDatagram::unbound()?, address)))
+				.inspect_err(|error| tracing::warn!(?path, %error, "could not open the notify socket"))
+				.ok()?;
+			Some(socket)
+		});
+		// Only honor the watchdog if it is meant for us, as systemd does.
+		let for_us =
+			std::env::var("WATCHDOG_PID").map_or(true, |pid| pid == std::process::id().to_string());
+		let watchdog_interval = std::env::var("WATCHDOG_USEC")
+			.ok()
+			.filter(|_| for_us && socket.is_some())
+			.and_then(|usec| usec.parse().ok())
+			.map(Duration::from_micros);
+		Self {
+			socket,
+			watchdog_interval,
+			last_watchdog: Instant::now(),
+			ready: false,
+			status: String::new(),
+		}
+	}
+
+	fn send(&self, message: &str) {
+		let Some((socket, address)) = &self.socket else {
+			return;
+		};
+		tracing::trace!(message, "notifying systemd");
+		if let Err(error) = socket.send_to_addr(message.as_bytes(), address) {
+			tracing::warn!(%error, "could not notify systemd");
+		}
+	}
+
+	/// Sends `READY=1`, once.
+	pub fn ready(&mut self) {
+		if !self.ready {
+			self.ready = true;
+			self.send("READY=1");
+		}
+	}
+
+	/// Sends `STATUS=`, if the status changed.
+	pub fn status(&mut self, status: String) {
+		if status != self.status {
+			self.send(&format!("STATUS={status}"));
+			self.status = status;
+		}
+	}
+
+	/// Half of the watchdog interval, as recommended by `sd_watchdog_enabled(3)`.
+	fn watchdog_period(&self) -> Option<Duration> {
+		self.watchdog_interval.map(|interval| interval / 2)
+	}
+
+	/// How long the main loop may wait before calling `watchdog`.
+	pub fn watchdog_due_in(&self) -> Option<Duration> {
+		self
+			.watchdog_period()
+			.map(|period| period.saturating_sub(self.last_watchdog.elapsed()))
+	}
+
+	/// Sends `WATCHDOG=1` if it is due.
+	pub fn watchdog(&mut self) {
+		let Some(period) = self.watchdog_period() else {
+			return;
+		};
+		if self.last_watchdog.elapsed() >= period {
+			self.last_watchdog = Instant::now();
+			self.send("WATCHDOG=1");
+		}
+	}
+}
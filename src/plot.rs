@@ -0,0 +1,395 @@
+//! `rustshift plot`, which draws the schedule as an SVG file or as a chart in the terminal.
+//!
+//! The values come from `get_config`, like in the daemon, so the plot shows exactly what the daemon does while not dimmed.
+//! The day is today in the local time zone, and the times are on the wall clock, like in `rustshift query`.
+
+use std::fmt::Write as _;
+
+use time::{Date, Duration, OffsetDateTime};
+
+use crate::cli::{Args, PlotArgs};
+use crate::clock::TimeZone;
+use crate::error::Error;
+use crate::query::system_time_zone;
+use crate::{get_config, schedule_position};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+struct Schedule {
+	/// In Kelvins, one per minute starting at midnight.
+	temperatures: Vec<f32>,
+	/// If there is a tint, which replaces the temperature, the red, green, and blue multipliers of the white point,
+	/// from 0.0 to 1.0, one per minute starting at midnight.
+	tint_channels: Option<[Vec<f32>; 3]>,
+	/// From 0.0 to 1.0, one per minute starting at midnight.
+	brightnesses: Vec<f32>,
+	/// The start and end of each transition, in minutes since midnight.
+	transitions: Vec<(u16, u16)>,
+}
+
+impl Schedule {
+	/// The minutes of `date` are on the wall clock, and are converted to the time that the schedule follows.
+	fn new(args: &Args, time_zone: &TimeZone, date: Date) -> Result<Self, Error> {
+		let mut schedule = Self {
+			temperatures: Vec::new(),
+			tint_channels: (args.day_tint.is_some() || args.night_tint.is_some()).then(Default::default),
+			brightnesses: Vec::new(),
+			transitions: Vec::new(),
+		};
+		for minute in 0..MINUTES_PER_DAY {
+			let local = date.midnight() + Duration::minutes(minute.into());
+			let time = time_zone
+				.to_schedule(time_zone.local_to_utc(local)?)?
+				.time();
+			let config = get_config(args, time, 0.0);
+			schedule.temperatures.push(config.temperature() as f32);
+			if let Some(tint_channels) = &mut schedule.tint_channels {
+				for (values, value) in tint_channels.iter_mut().zip(config.white_point_channels()) {
+					values.push(value);
+				}
+			}
+			schedule.brightnesses.push(config.brightness());
+			// The transition starts a minute before its progress becomes positive.
+			if schedule_position(time).0.is_some() {
+				match schedule.transitions.last_mut() {
+					Some((_start, end)) if *end == minute => *end = minute + 1,
+					_ => schedule
+						.transitions
+						.push((minute.saturating_sub(1), minute + 1)),
+				}
+			}
+		}
+		Ok(schedule)
+	}
+
+	/// The bottom and top of the temperature axis, in Kelvins, with some room below the lowest temperature.
+	fn temperature_range(&self) -> (f32, f32) {
+		let min = self
+			.temperatures
+			.iter()
+			.copied()
+			.fold(f32::INFINITY, f32::min);
+		let max = self.temperatures.iter().copied().fold(0.0, f32::max);
+		(
+			((min / 1000.0).floor() * 1000.0 - 1000.0).max(0.0),
+			(max / 1000.0).ceil() * 1000.0,
+		)
+	}
+}
+
+fn format_minute(minute: u16) -> String {
+	format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+pub fn plot(args: &Args, plot_args: &PlotArgs) -> Result<(), String> {
+	let schedule = system_time_zone()
+		.and_then(|time_zone| {
+			let today = time_zone.to_local(OffsetDateTime::now_utc())?.date();
+			Schedule::new(args, &time_zone, today)
+		})
+		.map_err(|error| error.to_string())?;
+	if let Some(path) = &plot_args.output {
+		std::fs::write(path, svg(&schedule))
+			.map_err(|error| format!("could not write {}: {error}", path.display()))
+	} else {
+		print!("{}", terminal_chart(&schedule));
+		Ok(())
+	}
+}
+
+const CHANNEL_NAMES: [&str; 3] = ["red", "green", "blue"];
+
+const CHART_COLUMNS: u16 = 72;
+const CHART_ROWS: usize = 8;
+const MINUTES_PER_COLUMN: u16 = MINUTES_PER_DAY / CHART_COLUMNS;
+/// Indexed by eighths of a row.
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+fn terminal_chart(schedule: &Schedule) -> String {
+	let (temperature_min, temperature_max) = schedule.temperature_range();
+	let mut out = String::new();
+	if let Some(tint_channels) = &schedule.tint_channels {
+		for (name, values) in CHANNEL_NAMES.into_iter().zip(tint_channels) {
+			chart(
+				&mut out,
+				&format!("White point ({name})"),
+				values,
+				(0.0, 1.0),
+				&["100%".into(), "0%".into()],
+				&schedule.transitions,
+			);
+			out.push('\n');
+		}
+	} else {
+		chart(
+			&mut out,
+			"Temperature",
+			&schedule.temperatures,
+			(temperature_min, temperature_max),
+			&[format!("{temperature_max}K"), format!("{temperature_min}K")],
+			&schedule.transitions,
+		);
+		out.push('\n');
+	}
+	chart(
+		&mut out,
+		"Brightness",
+		&schedule.brightnesses,
+		(0.0, 1.0),
+		&["100%".into(), "0%".into()],
+		&schedule.transitions,
+	);
+	out.push('\n');
+	let transitions = schedule
+		.transitions
+		.iter()
+		.map(|&(start, end)| format!("{}–{}", format_minute(start), format_minute(end)))
+		.collect::<Vec<_>>();
+	if transitions.is_empty() {
+		out.push_str("No transitions.\n");
+	} else {
+		_ = writeln!(out, "Transitions (┬): {}", transitions.join(", "));
+	}
+	out
+}
+
+/// Draws `values` (one per minute) as bars from `min` to `max`, with labels for the top and bottom.
+fn chart(
+	out: &mut String,
+	title: &str,
+	values: &[f32],
+	(min, max): (f32, f32),
+	[top_label, bottom_label]: &[String; 2],
+	transitions: &[(u16, u16)],
+) {
+	let label_width = top_label.chars().count().max(bottom_label.chars().count());
+	// Each column shows the value in its middle.
+	let eighths = (0..CHART_COLUMNS)
+		.map(|column| {
+			let value = values[usize::from(column * MINUTES_PER_COLUMN + MINUTES_PER_COLUMN / 2)];
+			((value - min) / (max - min) * (CHART_ROWS * 8) as f32).round() as usize
+		})
+		.collect::<Vec<_>>();
+
+	_ = writeln!(out, "{title}");
+	for row in (0..CHART_ROWS).rev() {
+		let label = match row {
+			_ if row == CHART_ROWS - 1 => top_label.as_str(),
+			0 => bottom_label.as_str(),
+			_ => "",
+		};
+		_ = write!(out, "{label:>label_width$} │");
+		for &eighths in &eighths {
+			out.push(BLOCKS[eighths.saturating_sub(row * 8).min(8)]);
+		}
+		out.push('\n');
+	}
+
+	_ = write!(out, "{:label_width$} └", "");
+	for column in 0..CHART_COLUMNS {
+		let start = column * MINUTES_PER_COLUMN;
+		let end = start + MINUTES_PER_COLUMN;
+		let in_transition = transitions
+			.iter()
+			.any(|&(transition_start, transition_end)| transition_start < end && start < transition_end);
+		out.push(if in_transition { '┬' } else { '─' });
+	}
+	out.push('\n');
+
+	// A label every six hours, centered on its column.
+	let mut axis = " ".repeat(label_width + 1);
+	for hour in (0..=24).step_by(6) {
+		let column = label_width + 2 + usize::from(hour * 60 / MINUTES_PER_COLUMN);
+		axis.push_str(&" ".repeat((column - 1).saturating_sub(axis.chars().count())));
+		_ = write!(axis, "{hour:02}");
+	}
+	_ = writeln!(out, "{axis}");
+}
+
+const SVG_WIDTH: f32 = 960.0;
+const SVG_HEIGHT: f32 = 360.0;
+const PLOT_LEFT: f32 = 70.0;
+const PLOT_RIGHT: f32 = SVG_WIDTH - 60.0;
+const PLOT_TOP: f32 = 40.0;
+const PLOT_BOTTOM: f32 = SVG_HEIGHT - 40.0;
+const TEMPERATURE_COLOR: &str = "#e8743b";
+const BRIGHTNESS_COLOR: &str = "#3b6fe8";
+/// For the white point's channels, which replace the temperature if there is a tint.
+const CHANNEL_COLORS: [&str; 3] = ["#d62728", "#2ca02c", "#1f4e9c"];
+
+fn svg_x(minute: f32) -> f32 {
+	PLOT_LEFT + minute / f32::from(MINUTES_PER_DAY) * (PLOT_RIGHT - PLOT_LEFT)
+}
+
+/// `fraction` is from 0.0 at the bottom to 1.0 at the top.
+fn svg_y(fraction: f32) -> f32 {
+	PLOT_BOTTOM - fraction * (PLOT_BOTTOM - PLOT_TOP)
+}
+
+fn svg_polyline(out: &mut String, values: &[f32], (min, max): (f32, f32), style: &str) {
+	let points = values
+		.iter()
+		.enumerate()
+		.map(|(minute, value)| {
+			format!(
+				"{:.1},{:.1}",
+				svg_x(minute as f32),
+				svg_y((value - min) / (max - min))
+			)
+		})
+		.collect::<Vec<_>>();
+	_ = writeln!(
+		out,
+		r#"<polyline points="{}" fill="none" {style}/>"#,
+		points.join(" ")
+	);
+}
+
+/// Temperature is on the left axis, and brightness on the right.
+fn svg(schedule: &Schedule) -> String {
+	let (temperature_min, temperature_max) = schedule.temperature_range();
+	let mut out = String::new();
+	_ = writeln!(
+		out,
+		r#"<svg xmlns="http://www.w3.org/2000/svg" width="{SVG_WIDTH}" height="{SVG_HEIGHT}" font-family="sans-serif" font-size="12">"#
+	);
+	_ = writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#);
+
+	for &(start, end) in &schedule.transitions {
+		let (left, right) = (svg_x(start.into()), svg_x(end.into()));
+		_ = writeln!(
+			out,
+			r##"<rect x="{left:.1}" y="{PLOT_TOP}" width="{:.1}" height="{}" fill="#eeeeee"><title>Transition {}–{}</title></rect>"##,
+			right - left,
+			PLOT_BOTTOM - PLOT_TOP,
+			format_minute(start),
+			format_minute(end),
+		);
+	}
+
+	// Hours
+	for hour in (0..=24).step_by(3) {
+		let x = svg_x((hour * 60) as f32);
+		_ = writeln!(
+			out,
+			r##"<line x1="{x:.1}" y1="{PLOT_TOP}" x2="{x:.1}" y2="{PLOT_BOTTOM}" stroke="#dddddd"/>"##
+		);
+		_ = writeln!(
+			out,
+			r#"<text x="{x:.1}" y="{}" text-anchor="middle">{hour:02}:00</text>"#,
+			PLOT_BOTTOM + 18.0
+		);
+	}
+	// Temperatures (or the white point's channels) and brightnesses, at the same heights.
+	for step in 0..=4 {
+		let fraction = step as f32 / 4.0;
+		let y = svg_y(fraction);
+		_ = writeln!(
+			out,
+			r##"<line x1="{PLOT_LEFT}" y1="{y:.1}" x2="{PLOT_RIGHT}" y2="{y:.1}" stroke="#dddddd"/>"##
+		);
+		let label = if schedule.tint_channels.is_some() {
+			format!("{:.0}%", fraction * 100.0)
+		} else {
+			format!(
+				"{:.0}K",
+				crate::util::lerp(temperature_min, temperature_max, fraction)
+			)
+		};
+		_ = writeln!(
+			out,
+			r#"<text x="{}" y="{:.1}" text-anchor="end" fill="{TEMPERATURE_COLOR}">{label}</text>"#,
+			PLOT_LEFT - 6.0,
+			y + 4.0,
+		);
+		_ = writeln!(
+			out,
+			r#"<text x="{}" y="{:.1}" fill="{BRIGHTNESS_COLOR}">{:.0}%</text>"#,
+			PLOT_RIGHT + 6.0,
+			y + 4.0,
+			fraction * 100.0,
+		);
+	}
+
+	svg_polyline(
+		&mut out,
+		&schedule.brightnesses,
+		(0.0, 1.0),
+		&format!(r#"stroke="{BRIGHTNESS_COLOR}" stroke-width="2" stroke-dasharray="6 4""#),
+	);
+	let title = if let Some(tint_channels) = &schedule.tint_channels {
+		for (values, color) in tint_channels.iter().zip(CHANNEL_COLORS) {
+			svg_polyline(
+				&mut out,
+				values,
+				(0.0, 1.0),
+				&format!(r#"stroke="{color}" stroke-width="2""#),
+			);
+		}
+		let channels = CHANNEL_NAMES
+			.into_iter()
+			.zip(CHANNEL_COLORS)
+			.map(|(name, color)| format!(r#"<tspan fill="{color}">{name}</tspan>"#))
+			.collect::<Vec<_>>();
+		format!(
+			r#"<tspan fill="{TEMPERATURE_COLOR}">White point</tspan> ({})"#,
+			channels.join(", ")
+		)
+	} else {
+		svg_polyline(
+			&mut out,
+			&schedule.temperatures,
+			(temperature_min, temperature_max),
+			&format!(r#"stroke="{TEMPERATURE_COLOR}" stroke-width="2""#),
+		);
+		format!(r#"<tspan fill="{TEMPERATURE_COLOR}">Temperature</tspan>"#)
+	};
+
+	_ = writeln!(
+		out,
+		r#"<text x="{PLOT_LEFT}" y="24" font-size="14">{title} and <tspan fill="{BRIGHTNESS_COLOR}">brightness</tspan> while not dimmed; transitions shaded</text>"#
+	);
+	out.push_str("</svg>\n");
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::Parser as _;
+	use time::macros::date;
+
+	use super::Schedule;
+	use crate::cli::Args;
+	use crate::clock::TimeZone;
+
+	/// Central European Time, with summer time from the last Sunday in March to the last Sunday in October.
+	const CET: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+	#[test]
+	fn transitions() {
+		let args = Args::parse_from(["rustshift"]);
+		let time_zone = TimeZone::new(CET).unwrap();
+		let schedule = Schedule::new(&args, &time_zone, date!(2026 - 06 - 01)).unwrap();
+		// 07:45–08:15 and 19:45–20:15
+		assert_eq!(schedule.transitions, [(465, 495), (1185, 1215)]);
+		assert_eq!(schedule.temperature_range(), (2000.0, 7000.0));
+		assert!(schedule.tint_channels.is_none());
+
+		// Without daylight savings time, the schedule is an hour ahead of the wall clock.
+		let schedule = Schedule::new(&args, &time_zone, date!(2026 - 01 - 15)).unwrap();
+		assert_eq!(schedule.transitions, [(405, 435), (1125, 1155)]);
+	}
+
+	#[test]
+	#[allow(clippy::float_cmp)] // Exact, since the tint is used as it is.
+	fn tint_replaces_temperature() {
+		let args = Args::parse_from(["rustshift", "--night-tint", "rgb:1.0,0.5,0.25"]);
+		let time_zone = TimeZone::new(CET).unwrap();
+		let schedule = Schedule::new(&args, &time_zone, date!(2026 - 06 - 01)).unwrap();
+		let [red, green, blue] = schedule.tint_channels.unwrap();
+		// The night tint at midnight, and the day temperature's white point at noon.
+		assert_eq!([red[0], green[0], blue[0]], [1.0, 0.5, 0.25]);
+		assert!(blue[720] > 0.9);
+		assert_eq!(red.len(), 1440);
+	}
+}
@@ -0,0 +1,126 @@
+//! `rustshift preview`, which replays the schedule on the outputs at accelerated speed.
+
+use std::io::Write;
+
+use time::{OffsetDateTime, Time};
+
+use crate::cli::{Args, PreviewArgs};
+use crate::clock::{Clock as _, SimulatedClock};
+use crate::color::Config;
+use crate::dbus_time::DbusTime;
+use crate::error::Error;
+use crate::event_loop::EventLoop;
+use crate::{backend, connect_wayland, load_calibrations, Daemon};
+
+pub fn run(args: &Args, preview_args: &PreviewArgs) -> Result<(), Error> {
+	let calibrations = load_calibrations(args)?;
+
+	let connection = connect_wayland(args)?;
+	// This has to happen before any threads are spawned, including by zbus.
+	let mut event_loop = EventLoop::new(connection.clone())?;
+
+	let dbus_time = DbusTime::connect()?;
+	let backend = backend::open(args.backend, event_loop.sender(), connection.as_ref())?;
+
+	let start = dbus_time.now_utc();
+	let end = start + time::Duration::DAY;
+	let clock = SimulatedClock::new(start, &dbus_time.time_zone()?).with_speed(preview_args.speed());
+	let mut daemon = Daemon::new(args, &clock, calibrations, backend, None);
+
+	// When this returns, dropping the backend resets the gamma, or restores it on X11 and DRM, like when the daemon exits.
+	// The daemon applies the schedule again once it is started.
+	replay(
+		&mut daemon,
+		&mut event_loop,
+		&clock,
+		end,
+		&mut std::io::stdout(),
+	)
+}
+
+/// Runs the daemon until `clock` reaches `end` or it is interrupted, and prints the updates to `out`.
+fn replay(
+	daemon: &mut Daemon<'_>,
+	event_loop: &mut EventLoop,
+	clock: &SimulatedClock,
+	end: OffsetDateTime,
+	out: &mut impl Write,
+) -> Result<(), Error> {
+	let mut last_printed = None;
+	while clock.now_utc() < end {
+		let timeout = daemon.timeout();
+		let event = event_loop.next(&mut *daemon.backend, timeout)?;
+		if daemon.handle_event(event)?.is_break() {
+			writeln!(out, "interrupted").map_err(Error::io("could not print the update"))?;
+			break;
+		}
+		if let Some((time, config)) = daemon.last_update {
+			// Every change is printed during transitions, and otherwise every 15 minutes.
+			let due = last_printed.is_none_or(|(last_time, last_config): (Time, Config)| {
+				(time.hour(), time.minute() / 15) != (last_time.hour(), last_time.minute() / 15)
+					|| config.different_from(last_config)
+			});
+			if due {
+				writeln!(
+					out,
+					"{:02}:{:02}  {}K, {:.0}% brightness",
+					time.hour(),
+					time.minute(),
+					config.temperature(),
+					config.brightness() * 100.0
+				)
+				.map_err(Error::io("could not print the update"))?;
+				last_printed = Some((time, config));
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::Parser as _;
+	use time::macros::datetime;
+
+	use super::replay;
+	use crate::cli::Args;
+	use crate::clock::{Clock as _, SimulatedClock};
+	use crate::event_loop::EventLoop;
+	use crate::{dry_run, Daemon};
+
+	#[test]
+	fn replays_a_day() {
+		let args = Args::parse_from(["rustshift"]);
+		let start = datetime!(2026-06-01 00:00 UTC);
+		let end = start + time::Duration::DAY;
+		// The day in about a second.
+		let clock = SimulatedClock::new(start, "UTC").with_speed(86_400.0);
+		let mut event_loop = EventLoop::new(None).unwrap();
+		let backend = dry_run::Backend::with_output(&event_loop.sender(), Box::new(std::io::sink()));
+		let mut daemon = Daemon::new(&args, &clock, Vec::new(), Box::new(backend), None);
+
+		let mut out = Vec::new();
+		replay(&mut daemon, &mut event_loop, &clock, end, &mut out).unwrap();
+		assert!(clock.now_utc() >= end);
+		let out = String::from_utf8(out).unwrap();
+		let times = out
+			.lines()
+			.map(|line| line.split_once("  ").unwrap().0)
+			.collect::<Vec<_>>();
+		// The schedule time is an hour ahead of UTC.
+		assert_eq!(times[0], "01:00");
+		assert!(out.starts_with("01:00  3500K, 100% brightness\n"), "{out}");
+		// Every change during the evening transition, and otherwise one line per 15 minutes.
+		let transition = times
+			.iter()
+			.filter(|time| ("19:46".."20:15").contains(time))
+			.count();
+		assert!(transition >= 10, "{out}");
+		let afternoon = times
+			.iter()
+			.filter(|time| ("13:00".."16:00").contains(time))
+			.count();
+		assert!(afternoon <= 12, "{out}");
+		assert!(out.ends_with("3500K, 100% brightness\n"), "{out}");
+	}
+}
@@ -0,0 +1,187 @@
+//! `rustshift query`, which prints what the schedule does at a given time, e.g., for scripts.
+
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::cli::{Args, QueryArgs};
+use crate::clock::{Clock as _, LocalClock, TimeZone};
+use crate::dbus_time::DbusTime;
+use crate::error::Error;
+use crate::status::{json_string, Class};
+use crate::{get_config, schedule_position, surrounding_transitions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum QueryFormat {
+	/// One `name: value` line per field.
+	#[default]
+	Plain,
+	/// A JSON object with `at`, `temperature`, `brightness`, `period`, `previous_transition`, and `next_transition`,
+	/// with times in RFC 3339 format.
+	Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Answer {
+	/// In local time.
+	at: OffsetDateTime,
+	temperature: u32,
+	/// In percent.
+	brightness: u32,
+	/// `Day`, `Night`, or `Transition`.
+	period: Class,
+	/// When the last transition at or before `at` started, in local time.
+	previous_transition: OffsetDateTime,
+	/// When the next transition after `at` starts, in local time.
+	next_transition: OffsetDateTime,
+}
+
+impl Answer {
+	/// Like the daemon's config while not dimmed or paused.
+	fn new(args: &Args, time_zone: &TimeZone, at: OffsetDateTime) -> Result<Self, Error> {
+		let schedule = time_zone.to_schedule(at)?;
+		let time = schedule.time();
+		let config = get_config(args, time, 0.0);
+		let period = match schedule_position(time) {
+			(Some(_), _) => Class::Transition,
+			(None, true) => Class::Day,
+			(None, false) => Class::Night,
+		};
+		let (previous_transition, next_transition) = surrounding_transitions(time_zone, schedule)?;
+
+		Ok(Self {
+			at: time_zone.to_local(at)?,
+			temperature: config.temperature(),
+			brightness: (config.brightness() * 100.0).round() as u32,
+			period,
+			previous_transition,
+			next_transition,
+		})
+	}
+
+	fn format(&self, format: QueryFormat) -> String {
+		match format {
+			QueryFormat::Plain => {
+				let time = |datetime: OffsetDateTime| {
+					datetime
+						.format(format_description!(
+							"[year]-[month]-[day] [hour]:[minute] [offset_hour sign:mandatory]:[offset_minute]"
+						))
+						.unwrap()
+				};
+				format!(
+					"at: {}\ntemperature: {}K\nbrightness: {}%\nperiod: {}\nprevious transition: {}\nnext transition: {}",
+					time(self.at),
+					self.temperature,
+					self.brightness,
+					self.period.name(),
+					time(self.previous_transition),
+					time(self.next_transition),
+				)
+			}
+			QueryFormat::Json => {
+				let time = |datetime: OffsetDateTime| json_string(&datetime.format(&Rfc3339).unwrap());
+				format!(
+					r#"{{"at":{},"temperature":{},"brightness":{},"period":{},"previous_transition":{},"next_transition":{}}}"#,
+					time(self.at),
+					self.temperature,
+					self.brightness,
+					json_string(self.period.name()),
+					time(self.previous_transition),
+					time(self.next_transition),
+				)
+			}
+		}
+	}
+}
+
+/// The system time zone from timedated, or from `TZ` or `/etc/localtime` if there is no system bus.
+pub fn system_time_zone() -> Result<TimeZone, Error> {
+	// Like dry runs, queries and plots should also work where there is no system bus.
+	let name = match DbusTime::connect() {
+		Ok(dbus_time) => dbus_time.time_zone(),
+		Err(error) => {
+			tracing::debug!(%error, "using the time zone from TZ or /etc/localtime");
+			LocalClock.time_zone()
+		}
+	}?;
+	TimeZone::new(&name)
+}
+
+pub fn query(args: &Args, query_args: &QueryArgs) -> Result<(), String> {
+	let time_zone = system_time_zone().map_err(|error| error.to_string())?;
+	let at = match query_args.at {
+		Some(local) => time_zone.local_to_utc(local),
+		None => Ok(OffsetDateTime::now_utc()),
+	}
+	.map_err(|error| error.to_string())?;
+
+	let answer = Answer::new(args, &time_zone, at).map_err(|error| error.to_string())?;
+	println!("{}", answer.format(query_args.format));
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use clap::Parser as _;
+	use time::macros::datetime;
+
+	use super::{Answer, QueryFormat};
+	use crate::cli::Args;
+	use crate::clock::TimeZone;
+	use crate::status::Class;
+
+	/// Central European Time, with summer time from the last Sunday in March to the last Sunday in October.
+	const CET: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+	fn answer(local: time::PrimitiveDateTime) -> Answer {
+		let time_zone = TimeZone::new(CET).unwrap();
+		let at = time_zone.local_to_utc(local).unwrap();
+		Answer::new(&Args::parse_from(["rustshift"]), &time_zone, at).unwrap()
+	}
+
+	#[test]
+	fn winter_evening() {
+		// Without summer time, the schedule runs an hour ahead of the wall clock.
+		let answer = answer(datetime!(2026-12-21 18:50));
+		assert_eq!(answer.at, datetime!(2026-12-21 18:50 +1));
+		assert_eq!(answer.period, Class::Transition);
+		assert_eq!(answer.brightness, 100);
+		assert_eq!(answer.previous_transition, datetime!(2026-12-21 18:45 +1));
+		assert_eq!(answer.next_transition, datetime!(2026-12-22 06:45 +1));
+		assert_eq!(
+			answer.format(QueryFormat::Json),
+			format!(
+				r#"{{"at":"2026-12-21T18:50:00+01:00","temperature":{},"brightness":100,"period":"transition","previous_transition":"2026-12-21T18:45:00+01:00","next_transition":"2026-12-22T06:45:00+01:00"}}"#,
+				answer.temperature
+			)
+		);
+	}
+
+	#[test]
+	fn summer_night() {
+		let answer = answer(datetime!(2026-06-01 03:00));
+		assert_eq!(answer.period, Class::Night);
+		assert_eq!(answer.previous_transition, datetime!(2026-05-31 19:45 +2));
+		assert_eq!(answer.next_transition, datetime!(2026-06-01 07:45 +2));
+	}
+
+	#[test]
+	fn dst_changes() {
+		let time_zone = TimeZone::new(CET).unwrap();
+		// Skipped, so moved forward to 03:30 summer time.
+		assert_eq!(
+			time_zone.local_to_utc(datetime!(2026-03-29 02:30)).unwrap(),
+			datetime!(2026-03-29 01:30 UTC)
+		);
+		// Twice, so the first one, in summer time.
+		assert_eq!(
+			time_zone.local_to_utc(datetime!(2026-10-25 02:30)).unwrap(),
+			datetime!(2026-10-25 00:30 UTC)
+		);
+		assert_eq!(
+			time_zone.local_to_utc(datetime!(2026-10-25 12:00)).unwrap(),
+			datetime!(2026-10-25 11:00 UTC)
+		);
+	}
+}
@@ -0,0 +1,317 @@
+//! Exporting ramps to files, and importing curves from them.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::cli::{Args, DumpRampsArgs};
+use crate::color::{Config, Curves, Filters, Ramps};
+use crate::icc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+	/// One line per ramp index with the red, green, and blue values, preceded by a header.
+	Csv,
+	/// An object with `red`, `green`, and `blue` arrays.
+	Json,
+	/// A 1D LUT in the Adobe/Resolve `.cube` format.
+	Cube,
+	/// Native-endian `u16`s, all red values followed by all green values followed by all blue values.
+	/// This is exactly what is sent to the compositor.
+	Raw,
+}
+
+pub fn write(ramps: &Ramps, format: Format, mut writer: impl Write) -> std::io::Result<()> {
+	let [red, green, blue] = ramps.channels();
+	let rows = red
+		.iter()
+		.zip(green)
+		.zip(blue)
+		.map(|((r, g), b)| [*r, *g, *b]);
+	match format {
+		Format::Csv => {
+			writeln!(writer, "index,red,green,blue")?;
+			for (index, [r, g, b]) in rows.enumerate() {
+				writeln!(writer, "{index},{r},{g},{b}")?;
+			}
+		}
+		Format::Json => {
+			let array = |channel: &[u16]| {
+				channel
+					.iter()
+					.map(u16::to_string)
+					.collect::<Vec<_>>()
+					.join(",")
+			};
+			writeln!(
+				writer,
+				r#"{{"size":{},"red":[{}],"green":[{}],"blue":[{}]}}"#,
+				ramps.ramp_size(),
+				array(red),
+				array(green),
+				array(blue),
+			)?;
+		}
+		Format::Cube => {
+			writeln!(writer, "TITLE \"rustshift\"")?;
+			writeln!(writer, "LUT_1D_SIZE {}", ramps.ramp_size())?;
+			let normalize = |value: u16| f32::from(value) / f32::from(u16::MAX);
+			for [r, g, b] in rows {
+				writeln!(
+					writer,
+					"{:.6} {:.6} {:.6}",
+					normalize(r),
+					normalize(g),
+					normalize(b)
+				)?;
+			}
+		}
+		Format::Raw => writer.write_all(ramps.as_bytes())?,
+	}
+	writer.flush()
+}
+
+/// Loads curves from a file written by `write`, in the format given by its extension (`.csv`, `.json`, or `.cube`),
+/// or otherwise in the `Format::Raw` format.
+pub fn load_curves(path: &Path) -> Result<Curves, String> {
+	let data =
+		std::fs::read(path).map_err(|error| format!("could not read {}: {error}", path.display()))?;
+	let format = match path.extension().and_then(|extension| extension.to_str()) {
+		Some("csv") => Format::Csv,
+		Some("json") => Format::Json,
+		Some("cube") => Format::Cube,
+		_ => Format::Raw,
+	};
+	let [red, green, blue] = parse(format, &data)
+		.map_err(|error| format!("could not load curves from {}: {error}", path.display()))?;
+
+	// The inverse of `f32_to_u16_full`.
+	let to_curve = |channel: Vec<u16>| {
+		channel
+			.into_iter()
+			.map(|value| f32::from(value) / (f32::from(u16::MAX) + 1.0))
+			.collect()
+	};
+	Curves::new(to_curve(red), to_curve(green), to_curve(blue))
+		.ok_or_else(|| format!("{} contains no values", path.display()))
+}
+
+/// Reads the channels back from a file written by `write`.
+fn parse(format: Format, data: &[u8]) -> Result<[Vec<u16>; 3], String> {
+	if format == Format::Raw {
+		return parse_raw(data);
+	}
+	let text = std::str::from_utf8(data).map_err(|error| format!("invalid UTF-8: {error}"))?;
+	match format {
+		Format::Csv => parse_csv(text),
+		Format::Json => parse_json(text),
+		Format::Cube => parse_cube(text),
+		Format::Raw => unreachable!(),
+	}
+}
+
+/// The index column is optional, and a header line is skipped.
+fn parse_csv(text: &str) -> Result<[Vec<u16>; 3], String> {
+	let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+	let mut lines = text
+		.lines()
+		.enumerate()
+		.filter(|(_index, line)| !line.trim().is_empty())
+		.peekable();
+	// Only the first line may be something other than numbers.
+	_ = lines.next_if(|(_index, line)| {
+		!line
+			.trim_start()
+			.starts_with(|ch: char| ch.is_ascii_digit())
+	});
+	for (index, line) in lines {
+		let error = || format!("line {}: expected 3 or 4 numbers, got {line:?}", index + 1);
+		let values = line
+			.split(',')
+			.map(|value| value.trim().parse::<u16>())
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|_| error())?;
+		let ([r, g, b] | [_, r, g, b]) = values[..] else {
+			return Err(error());
+		};
+		for (channel, value) in channels.iter_mut().zip([r, g, b]) {
+			channel.push(value);
+		}
+	}
+	Ok(channels)
+}
+
+/// Only handles the object written by `write`, with a `red`, `green`, and `blue` array of integers.
+fn parse_json(text: &str) -> Result<[Vec<u16>; 3], String> {
+	let channel = |name: &str| -> Result<Vec<u16>, String> {
+		let key = format!("\"{name}\"");
+		let array = text
+			.find(&key)
+			.map(|start| &text[start + key.len()..])
+			.and_then(|rest| rest.trim_start().strip_prefix(':'))
+			.and_then(|rest| rest.trim_start().strip_prefix('['))
+			.and_then(|rest| rest.split_once(']'))
+			.ok_or_else(|| format!("expected a {key} array"))?
+			.0;
+		array
+			.split(',')
+			.filter(|value| !value.trim().is_empty())
+			.map(|value| {
+				value
+					.trim()
+					.parse()
+					.map_err(|_| format!("invalid value {:?} in the {key} array", value.trim()))
+			})
+			.collect()
+	};
+	let [red, green, blue] = [channel("red")?, channel("green")?, channel("blue")?];
+	if red.len() != green.len() || red.len() != blue.len() {
+		return Err("the arrays have different lengths".into());
+	}
+	Ok([red, green, blue])
+}
+
+/// Handles 1D LUTs with the default domain of 0 to 1.
+fn parse_cube(text: &str) -> Result<[Vec<u16>; 3], String> {
+	let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+	let mut size = None;
+	for (index, line) in text.lines().enumerate() {
+		let line = line.trim();
+		let error = |message: &str| format!("line {}: {message}", index + 1);
+		if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") {
+			continue;
+		}
+		if let Some(value) = line.strip_prefix("LUT_1D_SIZE") {
+			size = Some(
+				value
+					.trim()
+					.parse::<usize>()
+					.map_err(|_| error("invalid LUT_1D_SIZE"))?,
+			);
+			continue;
+		}
+		if line.starts_with(|ch: char| ch.is_ascii_alphabetic()) {
+			return Err(error(&format!("unsupported keyword in {line:?}")));
+		}
+		let values = line
+			.split_whitespace()
+			.map(|value| {
+				value
+					.parse::<f32>()
+					.ok()
+					.filter(|value| (0.0..=1.0).contains(value))
+			})
+			.collect::<Option<Vec<_>>>();
+		let Some([r, g, b]) = values.and_then(|values| <[f32; 3]>::try_from(values).ok()) else {
+			return Err(error(&format!(
+				"expected 3 numbers from 0 to 1, got {line:?}"
+			)));
+		};
+		for (channel, value) in channels.iter_mut().zip([r, g, b]) {
+			channel.push((value * f32::from(u16::MAX)).round() as u16);
+		}
+	}
+	match size {
+		Some(size) if size == channels[0].len() => Ok(channels),
+		Some(size) => Err(format!(
+			"LUT_1D_SIZE is {size}, but there are {} entries",
+			channels[0].len()
+		)),
+		None => Err("missing LUT_1D_SIZE".into()),
+	}
+}
+
+fn parse_raw(data: &[u8]) -> Result<[Vec<u16>; 3], String> {
+	let values: Vec<u16> = data
+		.chunks(2)
+		.map(|bytes| {
+			bytes
+				.try_into()
+				.map(u16::from_ne_bytes)
+				.map_err(|_| "odd number of bytes".to_owned())
+		})
+		.collect::<Result<_, _>>()?;
+	if !values.len().is_multiple_of(3) {
+		return Err("the number of values is not a multiple of 3".into());
+	}
+	let ramp_size = values.len() / 3;
+	Ok([0, 1, 2].map(|channel| values[channel * ramp_size..][..ramp_size].to_vec()))
+}
+
+/// Implements `rustshift dump-ramps`.
+pub fn dump(args: &Args, dump_args: &DumpRampsArgs) -> Result<(), String> {
+	let config = Config::new(dump_args.temperature, dump_args.brightness)
+		.ok_or("brightness must be in the range 0 to 1")?
+		.with_white_point_model(args.white_point_model)
+		.with_tint(dump_args.tint)
+		.with_filters(Filters {
+			invert: args.invert,
+			reduce_color: args.reduce_color,
+		});
+	let curves = match (&dump_args.icc_profile, &dump_args.base_curve) {
+		(Some(path), _) => Some(icc::load_vcgt(path)?),
+		(None, Some(path)) => Some(load_curves(path)?),
+		(None, None) => None,
+	};
+
+	let mut ramps = Ramps::new(dump_args.size as usize);
+	config.generate_ramps(&mut ramps, curves.as_ref());
+	write(&ramps, dump_args.format, std::io::stdout().lock())
+		.map_err(|error| format!("could not write ramps: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse, write, Format};
+	use crate::color::{Config, Ramps};
+
+	#[test]
+	fn round_trip() {
+		let mut ramps = Ramps::new(256);
+		Config::new(3000, 0.8)
+			.unwrap()
+			.generate_ramps(&mut ramps, None);
+		for format in [Format::Csv, Format::Json, Format::Cube, Format::Raw] {
+			let mut data = Vec::new();
+			write(&ramps, format, &mut data).unwrap();
+			let channels = parse(format, &data).unwrap();
+			assert_eq!(
+				channels.each_ref().map(Vec::as_slice),
+				ramps.channels(),
+				"{format:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn malformed_csv() {
+		assert_eq!(
+			parse(Format::Csv, b"red,green,blue\n0,0,0\n\n65535,65535,65535\n").unwrap(),
+			[vec![0, 65535], vec![0, 65535], vec![0, 65535]]
+		);
+		assert_eq!(
+			parse(
+				Format::Csv,
+				b"index,red,green,blue\n0,0,0,0\n1,256,2S6,256\n2,512,512,512\n"
+			)
+			.unwrap_err(),
+			r#"line 3: expected 3 or 4 numbers, got "1,256,2S6,256""#
+		);
+		assert_eq!(
+			parse(Format::Csv, b"0,0,0\nred,green,blue\n").unwrap_err(),
+			r#"line 2: expected 3 or 4 numbers, got "red,green,blue""#
+		);
+		assert!(parse(Format::Csv, b"0,0\n").is_err());
+	}
+
+	#[test]
+	fn malformed_json_and_cube() {
+		assert!(parse(Format::Json, br#"{"red":[0,1],"green":[0,1]}"#).is_err());
+		assert!(parse(Format::Json, br#"{"red":[0,1],"green":[0,1],"blue":[0]}"#).is_err());
+		assert_eq!(
+			parse(Format::Cube, b"LUT_1D_SIZE 2\n0 0 0\n1 1.5 1\n").unwrap_err(),
+			r#"line 3: expected 3 numbers from 0 to 1, got "1 1.5 1""#
+		);
+		assert!(parse(Format::Cube, b"LUT_1D_SIZE 3\n0 0 0\n1 1 1\n").is_err());
+		assert!(parse(Format::Cube, b"LUT_3D_SIZE 2\n").is_err());
+	}
+}
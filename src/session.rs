@@ -0,0 +1,262 @@
+//! The logind session and seat state: whether the session is locked, and whether idling is inhibited.
+
+use std::time::Duration;
+
+use futures_lite::stream::{Boxed, StreamExt as _};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{dbus_proxy, CacheProperties};
+
+use crate::error::Error;
+use crate::Event;
+
+/// `auto` works for method calls, but signals are only emitted on the session's own path,
+/// which `Manager::get_session` resolves `auto` to.
+#[dbus_proxy(
+	interface = "org.freedesktop.login1.Session",
+	default_service = "org.freedesktop.login1",
+	default_path = "/org/freedesktop/login1/session/auto"
+)]
+pub trait Session {
+	fn set_brightness(&self, subsystem: &str, name: &str, brightness: u32) -> zbus::Result<()>;
+
+	/// Asks the screen locker to lock the session.
+	#[dbus_proxy(signal)]
+	fn lock(&self) -> zbus::Result<()>;
+
+	/// Asks the screen locker to unlock the session.
+	#[dbus_proxy(signal)]
+	fn unlock(&self) -> zbus::Result<()>;
+
+	/// Set by the screen locker while the session is locked.
+	#[dbus_proxy(property)]
+	fn locked_hint(&self) -> zbus::Result<bool>;
+}
+
+#[dbus_proxy(
+	interface = "org.freedesktop.login1.Manager",
+	default_service = "org.freedesktop.login1",
+	default_path = "/org/freedesktop/login1",
+	gen_blocking = false
+)]
+trait Manager {
+	/// `auto` is the caller's session, or the user's graphical session if the caller is not part of one, e.g., as a user service.
+	fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+	/// The colon-separated list of things that are currently inhibited, e.g., `sleep:idle`.
+	/// logind does not emit `PropertiesChanged` for it, so it has to be polled.
+	#[dbus_proxy(property)]
+	fn block_inhibited(&self) -> zbus::Result<String>;
+}
+
+/// How often `BlockInhibited` is read.
+const INHIBITOR_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What to do while the session is locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LockBehavior {
+	/// Keep following the schedule.
+	#[default]
+	Schedule,
+	/// Dim the screen as if by `rustshift ctl dim`.
+	Dim,
+	/// Remove the tint entirely, keeping only the brightness and filters.
+	Neutral,
+}
+
+/// `Event::SetLocked` whenever the session is locked or unlocked,
+/// either through the `Lock`/`Unlock` signals or the `LockedHint` property.
+pub async fn lock_events(dbus: &zbus::Connection) -> Result<Boxed<Event>, Error> {
+	let path = async { ManagerProxy::new(dbus).await?.get_session("auto").await }
+		.await
+		.map_err(Error::dbus("could not find the logind session"))?;
+	tracing::debug!(%path, "found the logind session");
+	let proxy = async { SessionProxy::builder(dbus).path(path)?.build().await }
+		.await
+		.map_err(Error::dbus("could not connect to the logind session"))?;
+
+	let lock = proxy
+		.receive_lock()
+		.await
+		.map_err(Error::dbus("could not subscribe to the logind Lock signal"))?;
+	let unlock = proxy.receive_unlock().await.map_err(Error::dbus(
+		"could not subscribe to the logind Unlock signal",
+	))?;
+	// The first change is the current value.
+	let locked_hint = proxy
+		.receive_locked_hint_changed()
+		.await
+		.then(|change| async move { change.get().await.ok() })
+		.filter_map(|locked| locked)
+		.inspect(|locked| tracing::trace!(locked, "got session lock update"));
+
+	Ok(
+		lock
+			.map(|_signal| true)
+			.or(unlock.map(|_signal| false))
+			.or(locked_hint)
+			.map(Event::SetLocked)
+			.boxed(),
+	)
+}
+
+/// `Event::SetIdleInhibited` whenever a logind idle inhibitor (e.g., `systemd-inhibit --what=idle`) is taken or released.
+pub async fn idle_inhibitor_events(dbus: &zbus::Connection) -> Result<Boxed<Event>, Error> {
+	poll_idle_inhibitors(dbus, INHIBITOR_POLL_INTERVAL).await
+}
+
+/// Reads `BlockInhibited` now and then every `interval`, and sends an event whenever idling becomes (un)inhibited.
+async fn poll_idle_inhibitors(
+	dbus: &zbus::Connection,
+	interval: Duration,
+) -> Result<Boxed<Event>, Error> {
+	// Without change signals, the cached value would never be updated.
+	let proxy = ManagerProxy::builder(dbus)
+		.cache_properties(CacheProperties::No)
+		.build()
+		.await
+		.map_err(Error::dbus("could not connect to the logind manager"))?;
+
+	let mut last = None;
+	Ok(
+		futures_lite::stream::once(())
+			.chain(async_io::Timer::interval(interval).map(drop))
+			.then(move |()| {
+				let proxy = proxy.clone();
+				async move { proxy.block_inhibited().await }
+			})
+			.filter_map(|inhibited| match inhibited {
+				Ok(inhibited) => Some(inhibited),
+				Err(error) => {
+					tracing::debug!(%error, "could not read the inhibitors");
+					None
+				}
+			})
+			.filter_map(move |inhibited| {
+				tracing::trace!(inhibited, "got inhibitors");
+				let idle_inhibited = inhibited.split(':').any(|what| what == "idle");
+				(last.replace(idle_inhibited) != Some(idle_inhibited))
+					.then_some(Event::SetIdleInhibited(idle_inhibited))
+			})
+			.boxed(),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use futures_lite::future::block_on;
+	use futures_lite::stream::{Boxed, StreamExt as _};
+	use zbus::dbus_interface;
+	use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+
+	use super::{lock_events, poll_idle_inhibitors};
+	use crate::test_dbus::connect;
+	use crate::Event;
+
+	/// Not `auto`, which logind resolves for method calls but never emits signals on.
+	const SESSION_PATH: &str = "/org/freedesktop/login1/session/_32";
+	const AUTO_PATH: &str = "/org/freedesktop/login1/session/auto";
+
+	struct FakeManager {
+		block_inhibited: Arc<Mutex<String>>,
+	}
+
+	#[dbus_interface(name = "org.freedesktop.login1.Manager")]
+	impl FakeManager {
+		#[allow(clippy::unused_self)]
+		fn get_session(&self, session_id: &str) -> OwnedObjectPath {
+			assert_eq!(session_id, "auto");
+			ObjectPath::try_from(SESSION_PATH).unwrap().into()
+		}
+
+		#[dbus_interface(property)]
+		fn block_inhibited(&self) -> String {
+			self.block_inhibited.lock().unwrap().clone()
+		}
+	}
+
+	struct FakeSession;
+
+	#[dbus_interface(name = "org.freedesktop.login1.Session")]
+	impl FakeSession {
+		#[allow(clippy::unused_self)]
+		#[dbus_interface(property)]
+		fn locked_hint(&self) -> bool {
+			false
+		}
+	}
+
+	fn start(block_inhibited: &Arc<Mutex<String>>) -> [zbus::blocking::Connection; 2] {
+		let block_inhibited = block_inhibited.clone();
+		connect(move |builder| {
+			builder
+				.serve_at("/org/freedesktop/login1", FakeManager { block_inhibited })?
+				.serve_at(SESSION_PATH, FakeSession)
+		})
+	}
+
+	/// The next event, or `None` if there is none within a few seconds.
+	fn next(events: &mut Boxed<Event>) -> Option<Event> {
+		block_on(futures_lite::future::or(events.next(), async {
+			async_io::Timer::after(Duration::from_secs(5)).await;
+			None
+		}))
+	}
+
+	#[test]
+	fn lock_signals_on_the_session_path() {
+		let [client, server] = start(&Arc::default());
+		let mut events = block_on(lock_events(client.inner())).unwrap();
+		// From `LockedHint`.
+		assert!(matches!(next(&mut events), Some(Event::SetLocked(false))));
+
+		let emit = |path: &str, signal: &str| {
+			server
+				.emit_signal(
+					None::<()>,
+					path,
+					"org.freedesktop.login1.Session",
+					signal,
+					&(),
+				)
+				.unwrap();
+		};
+		emit(SESSION_PATH, "Lock");
+		assert!(matches!(next(&mut events), Some(Event::SetLocked(true))));
+		// Only the session's own path counts.
+		emit(AUTO_PATH, "Unlock");
+		emit(SESSION_PATH, "Lock");
+		assert!(matches!(next(&mut events), Some(Event::SetLocked(true))));
+		emit(SESSION_PATH, "Unlock");
+		assert!(matches!(next(&mut events), Some(Event::SetLocked(false))));
+	}
+
+	#[test]
+	fn polls_idle_inhibitors() {
+		let block_inhibited = Arc::new(Mutex::new("sleep".to_owned()));
+		let [client, _server] = start(&block_inhibited);
+		let mut events = block_on(poll_idle_inhibitors(
+			client.inner(),
+			Duration::from_millis(10),
+		))
+		.unwrap();
+		assert!(matches!(
+			next(&mut events),
+			Some(Event::SetIdleInhibited(false))
+		));
+
+		// Without a change signal.
+		*block_inhibited.lock().unwrap() = "sleep:idle".into();
+		assert!(matches!(
+			next(&mut events),
+			Some(Event::SetIdleInhibited(true))
+		));
+		*block_inhibited.lock().unwrap() = String::new();
+		assert!(matches!(
+			next(&mut events),
+			Some(Event::SetIdleInhibited(false))
+		));
+	}
+}
@@ -0,0 +1,235 @@
+//! The daemon's status as reported to `rustshift ctl watch`, e.g., for status bars.
+//!
+//! The daemon writes one status line in the `Display` format to each watcher whenever the status changes,
+//! and `rustshift ctl watch` converts it to the requested format.
+
+use std::fmt::{self, Display, Formatter, Write as _};
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use time::Time;
+
+use crate::color::{parse_tint, Tint};
+use crate::control;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+	Day,
+	Night,
+	Transition,
+	Paused,
+	/// A tint was set with `rustshift ctl tint`.
+	Override,
+	Dimmed,
+}
+
+impl Class {
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Day => "day",
+			Self::Night => "night",
+			Self::Transition => "transition",
+			Self::Paused => "paused",
+			Self::Override => "override",
+			Self::Dimmed => "dimmed",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+	pub temperature: u32,
+	/// In percent.
+	pub brightness: u32,
+	pub class: Class,
+	/// When the next transition between day and night starts.
+	pub next_transition: Time,
+	/// The tint from `rustshift ctl tint`, which is shown instead of the temperature.
+	pub tint: Option<Tint>,
+}
+
+/// The tint, if any, comes last.
+impl Display for Status {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} {} {} {:02}:{:02}",
+			self.temperature,
+			self.brightness,
+			self.class.name(),
+			self.next_transition.hour(),
+			self.next_transition.minute(),
+		)?;
+		if let Some(tint) = self.tint {
+			write!(f, " {tint}")?;
+		}
+		Ok(())
+	}
+}
+
+impl FromStr for Status {
+	type Err = String;
+
+	fn from_str(input: &str) -> Result<Self, Self::Err> {
+		let error = || format!("invalid status line {input:?}");
+		let mut fields = input.split_whitespace().collect::<Vec<_>>();
+		let tint = match fields.len() {
+			5 => Some(parse_tint(fields.pop().unwrap()).map_err(|_| error())?),
+			_ => None,
+		};
+		let [temperature, brightness, class, next_transition] =
+			fields.try_into().map_err(|_| error())?;
+		let class = [
+			Class::Day,
+			Class::Night,
+			Class::Transition,
+			Class::Paused,
+			Class::Override,
+			Class::Dimmed,
+		]
+		.into_iter()
+		.find(|candidate| candidate.name() == class)
+		.ok_or_else(error)?;
+		let (hour, minute) = next_transition.split_once(':').ok_or_else(error)?;
+		Ok(Self {
+			temperature: temperature.parse().map_err(|_| error())?,
+			brightness: brightness.parse().map_err(|_| error())?,
+			class,
+			next_transition: Time::from_hms(
+				hour.parse().map_err(|_| error())?,
+				minute.parse().map_err(|_| error())?,
+				0,
+			)
+			.map_err(|_| error())?,
+			tint,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum WatchFormat {
+	/// A human-readable line.
+	#[default]
+	Plain,
+	/// A JSON object with `text`, `tooltip`, `class`, and `percentage`, for Waybar's `return-type: json`.
+	Waybar,
+}
+
+impl Status {
+	fn text(&self) -> String {
+		self
+			.tint
+			.map_or_else(|| format!("{}K", self.temperature), |tint| tint.to_string())
+	}
+
+	fn tooltip(&self) -> String {
+		format!(
+			"{}, {}% brightness ({})\nNext transition at {:02}:{:02}",
+			self.text(),
+			self.brightness,
+			self.class.name(),
+			self.next_transition.hour(),
+			self.next_transition.minute(),
+		)
+	}
+
+	pub fn format(&self, format: WatchFormat) -> String {
+		match format {
+			WatchFormat::Plain => self.tooltip().replace('\n', ", "),
+			WatchFormat::Waybar => format!(
+				r#"{{"text":{},"tooltip":{},"class":{},"percentage":{}}}"#,
+				json_string(&self.text()),
+				json_string(&self.tooltip()),
+				json_string(self.class.name()),
+				self.brightness,
+			),
+		}
+	}
+}
+
+pub fn json_string(value: &str) -> String {
+	let mut output = String::with_capacity(value.len() + 2);
+	output.push('"');
+	for ch in value.chars() {
+		match ch {
+			'"' => output.push_str("\\\""),
+			'\\' => output.push_str("\\\\"),
+			'\n' => output.push_str("\\n"),
+			ch if ch.is_control() => _ = write!(output, "\\u{:04x}", u32::from(ch)),
+			ch => output.push(ch),
+		}
+	}
+	output.push('"');
+	output
+}
+
+/// Implements `rustshift ctl watch`: prints the status whenever it changes, until the daemon exits.
+pub fn watch(format: WatchFormat) -> Result<(), String> {
+	let stream = control::request("watch")?;
+	let mut stdout = std::io::stdout().lock();
+	for line in stream.lines() {
+		let line = line.map_err(|error| format!("could not read status: {error}"))?;
+		let status: Status = line.parse()?;
+		writeln!(stdout, "{}", status.format(format))
+			.and_then(|()| stdout.flush())
+			.map_err(|error| format!("could not write status: {error}"))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use time::macros::time;
+
+	use super::{Class, Status, WatchFormat};
+	use crate::color::Tint;
+
+	fn status(class: Class, tint: Option<Tint>) -> Status {
+		Status {
+			temperature: 6500,
+			brightness: 80,
+			class,
+			next_transition: time!(19:45),
+			tint,
+		}
+	}
+
+	#[test]
+	fn round_trip() {
+		let tint = Tint::Rgb {
+			red: 1.0,
+			green: 0.75,
+			blue: 0.4,
+		};
+		for status in [
+			status(Class::Day, None),
+			status(Class::Override, Some(tint)),
+		] {
+			assert_eq!(status.to_string().parse(), Ok(status));
+		}
+		assert_eq!(
+			status(Class::Override, Some(tint)).to_string(),
+			"6500 80 override 19:45 rgb:1,0.75,0.4"
+		);
+		assert!("6500 80 day".parse::<Status>().is_err());
+		assert!("6500 80 override 19:45 rgb:2,0,0"
+			.parse::<Status>()
+			.is_err());
+	}
+
+	#[test]
+	fn reports_the_tint_override() {
+		let status = status(
+			Class::Override,
+			Some(Tint::Chromaticity { x: 0.48, y: 0.41 }),
+		);
+		assert_eq!(
+			status.format(WatchFormat::Plain),
+			"xy:0.48,0.41, 80% brightness (override), Next transition at 19:45"
+		);
+		assert_eq!(
+			status.format(WatchFormat::Waybar),
+			r#"{"text":"xy:0.48,0.41","tooltip":"xy:0.48,0.41, 80% brightness (override)\nNext transition at 19:45","class":"override","percentage":80}"#
+		);
+	}
+}
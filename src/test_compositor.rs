@@ -0,0 +1,306 @@
+//! An in-process Wayland compositor for tests, which advertises `wl_output`s and `zwlr_gamma_control_manager_v1`.
+//!
+//! The compositor runs on its own thread, since clients do blocking roundtrips.
+//! Every method waits until the compositor has handled it, so the next client roundtrip sees the result.
+
+use std::fs::File;
+use std::io::Read as _;
+use std::os::fd::AsFd as _;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use nix::poll::{poll, PollFd, PollFlags};
+use wayland_protocols_wlr::gamma_control::v1::server::{
+	zwlr_gamma_control_manager_v1, zwlr_gamma_control_v1,
+};
+use wayland_server::backend::{ClientData, GlobalId};
+use wayland_server::protocol::wl_output;
+use wayland_server::{
+	Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource as _,
+};
+
+/// Identifies an output within the test compositor.
+pub type TestOutputId = usize;
+
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+	pub name: Option<&'static str>,
+	pub description: Option<&'static str>,
+	pub gamma_size: u32,
+	/// Send `failed` instead of the gamma size, as if another client had gamma control.
+	pub fail: bool,
+}
+
+impl OutputConfig {
+	pub fn new(name: &'static str) -> Self {
+		Self {
+			name: Some(name),
+			description: None,
+			gamma_size: 256,
+			fail: false,
+		}
+	}
+
+	pub fn with_description(self, description: &'static str) -> Self {
+		Self {
+			description: Some(description),
+			..self
+		}
+	}
+
+	pub fn with_gamma_size(self, gamma_size: u32) -> Self {
+		Self { gamma_size, ..self }
+	}
+
+	pub fn failing(self) -> Self {
+		Self { fail: true, ..self }
+	}
+}
+
+enum Command {
+	Connect(UnixStream),
+	AddOutput(OutputConfig),
+	RemoveOutput(TestOutputId),
+}
+
+/// The gamma tables received for each output, as raw bytes.
+type Received = Arc<Mutex<Vec<(TestOutputId, Vec<u8>)>>>;
+
+pub struct TestCompositor {
+	commands: Sender<(Command, Sender<TestOutputId>)>,
+	received: Received,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl TestCompositor {
+	pub fn start() -> Self {
+		Self::start_inner(true)
+	}
+
+	/// Like a compositor that is not based on wlroots.
+	pub fn start_without_gamma_control() -> Self {
+		Self::start_inner(false)
+	}
+
+	fn start_inner(gamma_control: bool) -> Self {
+		let (commands, command_recv) = channel();
+		let received = Received::default();
+		let thread = std::thread::spawn({
+			let received = received.clone();
+			move || run(gamma_control, &command_recv, received)
+		});
+		Self {
+			commands,
+			received,
+			thread: Some(thread),
+		}
+	}
+
+	fn command(&self, command: Command) -> TestOutputId {
+		let (reply, reply_recv) = channel();
+		self.commands.send((command, reply)).unwrap();
+		reply_recv.recv().unwrap()
+	}
+
+	pub fn connect(&self) -> wayland_client::Connection {
+		let (server, client) = UnixStream::pair().unwrap();
+		self.command(Command::Connect(server));
+		wayland_client::Connection::from_socket(client).unwrap()
+	}
+
+	pub fn add_output(&self, config: OutputConfig) -> TestOutputId {
+		self.command(Command::AddOutput(config))
+	}
+
+	/// Removes the output's global, which sends `wl_registry.global_remove`.
+	pub fn remove_output(&self, output: TestOutputId) {
+		self.command(Command::RemoveOutput(output));
+	}
+
+	/// The gamma tables that have been set on the output, in order.
+	pub fn received_gamma(&self, output: TestOutputId) -> Vec<Vec<u8>> {
+		self
+			.received
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(id, _)| *id == output)
+			.map(|(_, gamma)| gamma.clone())
+			.collect()
+	}
+}
+
+impl Drop for TestCompositor {
+	fn drop(&mut self) {
+		// Disconnecting the channel stops the thread.
+		let (commands, _) = channel();
+		self.commands = commands;
+		if let Some(thread) = self.thread.take() {
+			thread.join().unwrap();
+		}
+	}
+}
+
+struct Output {
+	config: OutputConfig,
+	global: Option<GlobalId>,
+}
+
+struct State {
+	outputs: Vec<Output>,
+	received: Received,
+}
+
+struct TestClient;
+
+impl ClientData for TestClient {}
+
+fn run(
+	gamma_control: bool,
+	commands: &Receiver<(Command, Sender<TestOutputId>)>,
+	received: Received,
+) {
+	let mut display = Display::<State>::new().unwrap();
+	let handle = display.handle();
+	if gamma_control {
+		handle
+			.create_global::<State, zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1, ()>(1, ());
+	}
+	let mut state = State {
+		outputs: Vec::new(),
+		received,
+	};
+
+	loop {
+		loop {
+			let (command, reply) = match commands.try_recv() {
+				Ok(command) => command,
+				Err(std::sync::mpsc::TryRecvError::Empty) => break,
+				Err(std::sync::mpsc::TryRecvError::Disconnected) => return,
+			};
+			let id = match command {
+				Command::Connect(stream) => {
+					display
+						.handle()
+						.insert_client(stream, Arc::new(TestClient))
+						.unwrap();
+					0
+				}
+				Command::AddOutput(config) => {
+					let id = state.outputs.len();
+					let global = handle.create_global::<State, wl_output::WlOutput, TestOutputId>(4, id);
+					state.outputs.push(Output {
+						config,
+						global: Some(global),
+					});
+					id
+				}
+				Command::RemoveOutput(id) => {
+					if let Some(global) = state.outputs[id].global.take() {
+						handle.remove_global::<State>(global);
+					}
+					id
+				}
+			};
+			display.flush_clients().unwrap();
+			reply.send(id).unwrap();
+		}
+
+		display.dispatch_clients(&mut state).unwrap();
+		display.flush_clients().unwrap();
+		let fd = display.backend().poll_fd();
+		_ = poll(&mut [PollFd::new(&fd, PollFlags::POLLIN)], 10);
+	}
+}
+
+impl GlobalDispatch<wl_output::WlOutput, TestOutputId> for State {
+	fn bind(
+		state: &mut Self,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<wl_output::WlOutput>,
+		id: &TestOutputId,
+		data_init: &mut DataInit<'_, Self>,
+	) {
+		let output = data_init.init(resource, *id);
+		let config = &state.outputs[*id].config;
+		if let Some(name) = config.name {
+			output.name(name.into());
+		}
+		if let Some(description) = config.description {
+			output.description(description.into());
+		}
+		output.done();
+	}
+}
+
+impl Dispatch<wl_output::WlOutput, TestOutputId> for State {
+	fn request(
+		_state: &mut Self,
+		_client: &Client,
+		_resource: &wl_output::WlOutput,
+		_request: wl_output::Request,
+		_id: &TestOutputId,
+		_handle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, Self>,
+	) {
+	}
+}
+
+impl GlobalDispatch<zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1, ()> for State {
+	fn bind(
+		_state: &mut Self,
+		_handle: &DisplayHandle,
+		_client: &Client,
+		resource: New<zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1>,
+		_data: &(),
+		data_init: &mut DataInit<'_, Self>,
+	) {
+		data_init.init(resource, ());
+	}
+}
+
+impl Dispatch<zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1, ()> for State {
+	fn request(
+		state: &mut Self,
+		_client: &Client,
+		_resource: &zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+		request: zwlr_gamma_control_manager_v1::Request,
+		_data: &(),
+		_handle: &DisplayHandle,
+		data_init: &mut DataInit<'_, Self>,
+	) {
+		if let zwlr_gamma_control_manager_v1::Request::GetGammaControl { id, output } = request {
+			let output_id = *output.data::<TestOutputId>().unwrap();
+			let control = data_init.init(id, output_id);
+			let config = &state.outputs[output_id].config;
+			if config.fail {
+				control.failed();
+			} else {
+				control.gamma_size(config.gamma_size);
+			}
+		}
+	}
+}
+
+impl Dispatch<zwlr_gamma_control_v1::ZwlrGammaControlV1, TestOutputId> for State {
+	fn request(
+		state: &mut Self,
+		_client: &Client,
+		_resource: &zwlr_gamma_control_v1::ZwlrGammaControlV1,
+		request: zwlr_gamma_control_v1::Request,
+		output_id: &TestOutputId,
+		_handle: &DisplayHandle,
+		_data_init: &mut DataInit<'_, Self>,
+	) {
+		if let zwlr_gamma_control_v1::Request::SetGamma { fd } = request {
+			let mut gamma = Vec::new();
+			File::from(fd.as_fd().try_clone_to_owned().unwrap())
+				.read_to_end(&mut gamma)
+				.unwrap();
+			state.received.lock().unwrap().push((*output_id, gamma));
+		}
+	}
+}
@@ -0,0 +1,23 @@
+//! A private D-Bus connection for tests, on which fake services stand in for the ones on the real buses.
+
+use std::os::unix::net::UnixStream;
+
+use zbus::blocking::{Connection, ConnectionBuilder};
+
+/// Serves the fakes that `serve` adds over a peer-to-peer connection, and returns the client's and the server's end.
+pub fn connect(
+	serve: impl for<'a> FnOnce(ConnectionBuilder<'a>) -> zbus::Result<ConnectionBuilder<'a>>
+		+ Send
+		+ 'static,
+) -> [Connection; 2] {
+	let (server, client) = UnixStream::pair().unwrap();
+	let server = std::thread::spawn(move || {
+		let guid = zbus::Guid::generate();
+		serve(ConnectionBuilder::unix_stream(server).server(&guid).p2p())?.build()
+	});
+	let client = ConnectionBuilder::unix_stream(client)
+		.p2p()
+		.build()
+		.unwrap();
+	[client, server.join().unwrap().unwrap()]
+}
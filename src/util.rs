@@ -1,5 +1,5 @@
 use wayland_client::protocol::wl_registry;
-use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_client::{Connection, Dispatch, DispatchError, Proxy, QueueHandle};
 
 /// `t` should be in the range `0.0..=1.0` for a typical lerp,
 /// but does not strictly have to be.
@@ -13,7 +13,7 @@ pub fn lerp(from: f32, to: f32, t: f32) -> f32 {
 pub fn get_proxy<T: Proxy + 'static>(
 	connection: &Connection,
 	minimum_version: u32,
-) -> Option<(u32, T)> {
+) -> Result<Option<(u32, T)>, DispatchError> {
 	struct Helper<T> {
 		slot: Option<(u32, T)>,
 		ignored_handle: QueueHandle<Ignored>,
@@ -34,22 +34,19 @@ pub fn get_proxy<T: Proxy + 'static>(
 					name,
 					interface,
 					version: _,
-				} => {
-					if interface == T::interface().name {
-						let proxy = registry.bind(name, state.minimum_version, &state.ignored_handle, ());
-						state.slot = Some((name, proxy));
-					}
+				} if interface == T::interface().name => {
+					let proxy = registry.bind(name, state.minimum_version, &state.ignored_handle, ());
+					state.slot = Some((name, proxy));
 				}
-				wl_registry::Event::GlobalRemove { name: removed_name } => {
+				wl_registry::Event::GlobalRemove { name: removed_name }
 					if state
 						.slot
 						.as_ref()
-						.is_some_and(|(name, _proxy)| *name == removed_name)
-					{
-						state.slot = None;
-					}
+						.is_some_and(|(name, _proxy)| *name == removed_name) =>
+				{
+					state.slot = None;
 				}
-				_ => todo!(),
+				_ => {}
 			}
 		}
 	}
@@ -64,8 +61,8 @@ pub fn get_proxy<T: Proxy + 'static>(
 		ignored_handle: connection.new_event_queue().handle(),
 		minimum_version,
 	};
-	queue.roundtrip(&mut helper).unwrap();
-	helper.slot
+	queue.roundtrip(&mut helper)?;
+	Ok(helper.slot)
 }
 
 pub struct Ignored;
@@ -1,39 +1,163 @@
-use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
-use std::os::fd::AsFd;
-use std::sync::mpsc::SyncSender;
-
-use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
-use wayland_client::protocol::{wl_output, wl_registry};
-use wayland_client::{delegate_noop, Connection, Dispatch, QueueHandle};
-use wayland_protocols_wlr::gamma_control::v1::client::{
-	zwlr_gamma_control_manager_v1, zwlr_gamma_control_v1,
+use std::time::Duration;
+
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{
+	event_created_child, Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::idle_notify::v1::client::{
+	ext_idle_notification_v1, ext_idle_notifier_v1,
+};
+use wayland_protocols_plasma::idle::client::{org_kde_kwin_idle, org_kde_kwin_idle_timeout};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+	zwlr_foreign_toplevel_handle_v1, zwlr_foreign_toplevel_manager_v1,
 };
 
-use crate::color::{Config, Ramps};
-use crate::util::{cstr, get_proxy, TakeIfExt};
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::inhibit::Focus;
+use crate::util::get_proxy;
 use crate::Event;
 
-#[derive(Debug)]
-struct GammaControlIntermediate {
-	output: wl_output::WlOutput,
-	output_registry_name: u32,
-	output_description: Option<Box<str>>,
-	control: zwlr_gamma_control_v1::ZwlrGammaControlV1,
+/// A source of events from the Wayland connection, with its own event queue.
+///
+/// The event loop reads events from the connection, and then they are dispatched with `dispatch_pending`.
+pub trait WaylandSource {
+	fn dispatch_pending(&mut self) -> Result<(), DispatchError>;
+}
+
+struct Monitor<H: 'static> {
+	queue: EventQueue<H>,
+	helper: H,
 }
 
-struct Helper {
-	gamma_control_manager: zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
-	event_send: SyncSender<Event>,
-	intermediates: Vec<GammaControlIntermediate>,
-	done: bool,
+impl<H: 'static> WaylandSource for Monitor<H> {
+	fn dispatch_pending(&mut self) -> Result<(), DispatchError> {
+		self.queue.dispatch_pending(&mut self.helper).map(drop)
+	}
 }
 
 // Minimum versions
-const WL_OUTPUT_VERSION: u32 = 4;
-const ZWLR_GAMMA_CONTROL_MANAGER_V1_VERSION: u32 = 1;
+const WL_SEAT_VERSION: u32 = 1;
+const EXT_IDLE_NOTIFIER_V1_VERSION: u32 = 1;
+const ORG_KDE_KWIN_IDLE_VERSION: u32 = 1;
+/// Version 2 added the fullscreen state.
+const ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_VERSION: u32 = 2;
+
+struct IdleHelper {
+	event_send: EventSender,
+}
+
+impl IdleHelper {
+	fn set_idle(&self, idle: bool) {
+		tracing::debug!(idle, "idle state changed");
+		_ = self.event_send.send(Event::SetIdle(idle));
+	}
+}
+
+impl Dispatch<ext_idle_notification_v1::ExtIdleNotificationV1, ()> for IdleHelper {
+	fn event(
+		state: &mut Self,
+		_proxy: &ext_idle_notification_v1::ExtIdleNotificationV1,
+		event: ext_idle_notification_v1::Event,
+		_data: &(),
+		_conn: &Connection,
+		_handle: &QueueHandle<Self>,
+	) {
+		match event {
+			ext_idle_notification_v1::Event::Idled => state.set_idle(true),
+			ext_idle_notification_v1::Event::Resumed => state.set_idle(false),
+			_ => {}
+		}
+	}
+}
 
-impl Dispatch<wl_registry::WlRegistry, ()> for Helper {
+impl Dispatch<org_kde_kwin_idle_timeout::OrgKdeKwinIdleTimeout, ()> for IdleHelper {
+	fn event(
+		state: &mut Self,
+		_proxy: &org_kde_kwin_idle_timeout::OrgKdeKwinIdleTimeout,
+		event: org_kde_kwin_idle_timeout::Event,
+		_data: &(),
+		_conn: &Connection,
+		_handle: &QueueHandle<Self>,
+	) {
+		match event {
+			org_kde_kwin_idle_timeout::Event::Idle => state.set_idle(true),
+			org_kde_kwin_idle_timeout::Event::Resumed => state.set_idle(false),
+			_ => {}
+		}
+	}
+}
+
+/// Sends `Event::SetIdle` when the user has been idle for `timeout`, and again when they become active.
+///
+/// Uses `ext_idle_notifier_v1`, or `org_kde_kwin_idle` if the compositor does not support it.
+pub fn monitor_idle(
+	event_send: EventSender,
+	connection: &Connection,
+	timeout: Duration,
+) -> Result<Option<Box<dyn WaylandSource>>, Error> {
+	let queue = connection.new_event_queue();
+	let handle = queue.handle();
+
+	let Some((_, seat)) = get_proxy::<wl_seat::WlSeat>(connection, WL_SEAT_VERSION)? else {
+		tracing::warn!("no seat found, not monitoring idle state");
+		return Ok(None);
+	};
+	let timeout = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+	if let Some((_, notifier)) =
+		get_proxy::<ext_idle_notifier_v1::ExtIdleNotifierV1>(connection, EXT_IDLE_NOTIFIER_V1_VERSION)?
+	{
+		notifier.get_idle_notification(timeout, &seat, &handle, ());
+	} else if let Some((_, idle)) =
+		get_proxy::<org_kde_kwin_idle::OrgKdeKwinIdle>(connection, ORG_KDE_KWIN_IDLE_VERSION)?
+	{
+		idle.get_idle_timeout(&seat, timeout, &handle, ());
+	} else {
+		tracing::warn!(
+			"the compositor supports neither ext_idle_notifier_v1 nor org_kde_kwin_idle, not monitoring idle state"
+		);
+		return Ok(None);
+	}
+
+	let helper = IdleHelper { event_send };
+	Ok(Some(Box::new(Monitor { queue, helper })))
+}
+
+#[derive(Debug)]
+struct Toplevel {
+	handle: zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
+	app_id: String,
+	title: String,
+	activated: bool,
+	fullscreen: bool,
+}
+
+struct ToplevelHelper {
+	event_send: EventSender,
+	toplevels: Vec<Toplevel>,
+	focus: Option<Focus>,
+	found_manager: bool,
+}
+
+impl ToplevelHelper {
+	fn update_focus(&mut self) {
+		let focus = self
+			.toplevels
+			.iter()
+			.find(|toplevel| toplevel.activated)
+			.map(|toplevel| Focus {
+				app_id: toplevel.app_id.clone(),
+				title: toplevel.title.clone(),
+				fullscreen: toplevel.fullscreen,
+			});
+		if focus != self.focus {
+			self.focus.clone_from(&focus);
+			_ = self.event_send.send(Event::SetFocus(focus));
+		}
+	}
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for ToplevelHelper {
 	fn event(
 		state: &mut Self,
 		registry: &wl_registry::WlRegistry,
@@ -42,169 +166,124 @@ impl Dispatch<wl_registry::WlRegistry, ()> for Helper {
 		_conn: &Connection,
 		handle: &QueueHandle<Self>,
 	) {
-		match event {
-			wl_registry::Event::Global {
-				name,
-				interface,
-				version: _,
-			} => {
-				if interface == "wl_output" {
-					let output = registry.bind(name, WL_OUTPUT_VERSION, handle, ());
-					let control = state
-						.gamma_control_manager
-						.get_gamma_control(&output, handle, ());
-					let intermediate = GammaControlIntermediate {
-						output,
-						output_registry_name: name,
-						output_description: None,
-						control,
-					};
-					state.intermediates.push(intermediate);
-				}
-			}
-			wl_registry::Event::GlobalRemove { name } => {
-				state
-					.intermediates
-					.retain(|intermediate| intermediate.output_registry_name != name);
-				state.done |= state
-					.event_send
-					.send(Event::RemoveOutput {
-						output_registry_name: name,
-					})
-					.is_err();
+		if let wl_registry::Event::Global {
+			name,
+			interface,
+			version,
+		} = event
+		{
+			if interface
+				== zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1::interface().name
+				&& version >= ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_VERSION
+			{
+				registry.bind::<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, _, _>(
+					name,
+					ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_VERSION,
+					handle,
+					(),
+				);
+				state.found_manager = true;
 			}
-			_ => todo!(),
 		}
 	}
 }
 
-impl Dispatch<wl_output::WlOutput, ()> for Helper {
+impl Dispatch<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, ()>
+	for ToplevelHelper
+{
 	fn event(
 		state: &mut Self,
-		proxy: &wl_output::WlOutput,
-		event: wl_output::Event,
+		_proxy: &zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1,
+		event: zwlr_foreign_toplevel_manager_v1::Event,
 		_data: &(),
 		_conn: &Connection,
 		_handle: &QueueHandle<Self>,
 	) {
-		if let wl_output::Event::Description { description } = event {
-			if let Some(intermediate) = state
-				.intermediates
-				.iter_mut()
-				.find(|intermediate| intermediate.output == *proxy)
-			{
-				intermediate.output_description = Some(description.into());
+		match event {
+			zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+				state.toplevels.push(Toplevel {
+					handle: toplevel,
+					app_id: String::new(),
+					title: String::new(),
+					activated: false,
+					fullscreen: false,
+				});
 			}
+			zwlr_foreign_toplevel_manager_v1::Event::Finished => {
+				tracing::warn!("the compositor stopped sending toplevel information");
+				state.toplevels.clear();
+				state.update_focus();
+			}
+			_ => {}
 		}
 	}
-}
 
-delegate_noop!(Helper: ignore zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1);
+	event_created_child!(ToplevelHelper, zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, [
+		zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1, ()),
+	]);
+}
 
-impl Dispatch<zwlr_gamma_control_v1::ZwlrGammaControlV1, ()> for Helper {
+impl Dispatch<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1, ()> for ToplevelHelper {
 	fn event(
 		state: &mut Self,
-		proxy: &zwlr_gamma_control_v1::ZwlrGammaControlV1,
-		event: zwlr_gamma_control_v1::Event,
+		proxy: &zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
+		event: zwlr_foreign_toplevel_handle_v1::Event,
 		_data: &(),
 		_conn: &Connection,
 		_handle: &QueueHandle<Self>,
 	) {
-		let Some(intermediate) = state
-			.intermediates
-			.take_if(|intermediate| intermediate.control == *proxy)
+		if let zwlr_foreign_toplevel_handle_v1::Event::Closed = event {
+			state.toplevels.retain(|toplevel| toplevel.handle != *proxy);
+			proxy.destroy();
+			state.update_focus();
+			return;
+		}
+		let Some(toplevel) = state
+			.toplevels
+			.iter_mut()
+			.find(|toplevel| toplevel.handle == *proxy)
 		else {
 			return;
 		};
 		match event {
-			zwlr_gamma_control_v1::Event::GammaSize { size: ramp_size } => {
-				let control = GammaControl {
-					output_registry_name: intermediate.output_registry_name,
-					output_description: intermediate.output_description.unwrap(),
-					proxy: intermediate.control,
-					ramps: Ramps::new(ramp_size.try_into().unwrap()),
-					last_config: None,
+			zwlr_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+			zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+			zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+				let has_state = |wanted: zwlr_foreign_toplevel_handle_v1::State| {
+					states
+						.chunks_exact(4)
+						.any(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()) == wanted as u32)
 				};
-				state.done |= state.event_send.send(Event::AddOutput(control)).is_err();
-			}
-			zwlr_gamma_control_v1::Event::Failed => {
-				let description = intermediate.output_description.map_or_else(
-					|| "(not received)".into(),
-					|description| format!("{description:?}"),
-				);
-				panic!("gamma control failed for output with description {description}");
+				toplevel.activated = has_state(zwlr_foreign_toplevel_handle_v1::State::Activated);
+				toplevel.fullscreen = has_state(zwlr_foreign_toplevel_handle_v1::State::Fullscreen);
 			}
+			zwlr_foreign_toplevel_handle_v1::Event::Done => state.update_focus(),
 			_ => {}
 		}
 	}
 }
 
-pub fn monitor_outputs(event_send: SyncSender<Event>, connection: &Connection) {
+/// Sends `Event::SetFocus` whenever the focused (activated) toplevel or its app ID, title, or fullscreen state change.
+pub fn monitor_toplevels(
+	event_send: EventSender,
+	connection: &Connection,
+) -> Result<Option<Box<dyn WaylandSource>>, Error> {
 	let mut queue = connection.new_event_queue();
 	let handle = queue.handle();
 	let _registry = connection.display().get_registry(&handle, ());
 
-	let mut helper = Helper {
-		gamma_control_manager: get_proxy(connection, ZWLR_GAMMA_CONTROL_MANAGER_V1_VERSION)
-			.unwrap()
-			.1,
+	let mut helper = ToplevelHelper {
 		event_send,
-		intermediates: Vec::new(),
-		done: false,
+		toplevels: Vec::new(),
+		focus: None,
+		found_manager: false,
 	};
-	while !helper.done {
-		queue.blocking_dispatch(&mut helper).unwrap();
-	}
-}
-
-pub struct GammaControl {
-	output_registry_name: u32,
-	output_description: Box<str>,
-	proxy: zwlr_gamma_control_v1::ZwlrGammaControlV1,
-	ramps: Ramps,
-	last_config: Option<Config>,
-}
-
-impl std::fmt::Debug for GammaControl {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		// Ensure exhaustiveness.
-		let Self {
-			output_registry_name,
-			output_description,
-			proxy: _,
-			ramps: _,
-			last_config,
-		} = self;
-
-		f.debug_struct("GammaControl")
-			.field("output_registry_name", output_registry_name)
-			.field("output_description", output_description)
-			.field("last_config", last_config)
-			.finish_non_exhaustive()
-	}
-}
-
-impl GammaControl {
-	pub fn set_gamma(&mut self, config: Config) {
-		tracing::trace!(?self.output_description, ?config, "setting gamma");
-
-		let last_config = self.last_config.replace(config);
-		if last_config.is_some_and(|last_config| !config.different_from(last_config)) {
-			tracing::trace!(?self.output_description, new_config=?config, ?last_config, "new config is not different enough from last config");
-		}
-
-		let mut ramps_fd: File = memfd_create(cstr!("gamma-ramps"), MemFdCreateFlag::MFD_CLOEXEC)
-			.unwrap()
-			.into();
-		config.generate_ramps(&mut self.ramps);
-		ramps_fd.write_all(self.ramps.as_bytes()).unwrap();
-		ramps_fd.seek(SeekFrom::Start(0)).unwrap();
-		self.proxy.set_gamma(ramps_fd.as_fd());
-	}
-
-	#[inline]
-	#[must_use]
-	pub fn is_for_output(&self, id: u32) -> bool {
-		self.output_registry_name == id
+	queue.roundtrip(&mut helper)?;
+	if !helper.found_manager {
+		tracing::warn!(
+			"the compositor does not support zwlr_foreign_toplevel_manager_v1 version 2, inhibit rules will not apply"
+		);
+		return Ok(None);
 	}
+	Ok(Some(Box::new(Monitor { queue, helper })))
 }
@@ -0,0 +1,393 @@
+//! The `wlr` gamma backend, using the `zwlr_gamma_control_v1` Wayland protocol.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::fd::AsFd;
+
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use wayland_client::protocol::{wl_output, wl_registry};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_wlr::gamma_control::v1::client::{
+	zwlr_gamma_control_manager_v1, zwlr_gamma_control_v1,
+};
+
+use crate::backend::{GammaBackend, OutputId, OutputInfo};
+use crate::color::Ramps;
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::util::{cstr, get_proxy, TakeIfExt};
+use crate::Event;
+
+// Minimum versions
+const WL_OUTPUT_VERSION: u32 = 4;
+const ZWLR_GAMMA_CONTROL_MANAGER_V1_VERSION: u32 = 1;
+
+#[derive(Debug)]
+struct GammaControlIntermediate {
+	output: wl_output::WlOutput,
+	output_registry_name: u32,
+	output_name: Option<Box<str>>,
+	output_description: Option<Box<str>>,
+	control: zwlr_gamma_control_v1::ZwlrGammaControlV1,
+}
+
+struct Helper {
+	gamma_control_manager: zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+	event_send: EventSender,
+	/// Outputs whose gamma size is not known yet.
+	intermediates: Vec<GammaControlIntermediate>,
+	/// Keyed by the output's registry name, which is also its `OutputId`.
+	controls: Vec<(u32, zwlr_gamma_control_v1::ZwlrGammaControlV1)>,
+	/// Whether the outputs that existed at startup have been enumerated.
+	/// Failing to control any of those is fatal, but outputs that are added later are skipped instead.
+	enumerated: bool,
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for Helper {
+	fn event(
+		state: &mut Self,
+		registry: &wl_registry::WlRegistry,
+		event: wl_registry::Event,
+		_data: &(),
+		_conn: &Connection,
+		handle: &QueueHandle<Self>,
+	) {
+		match event {
+			wl_registry::Event::Global {
+				name,
+				interface,
+				version: _,
+			} if interface == "wl_output" => {
+				let output = registry.bind(name, WL_OUTPUT_VERSION, handle, ());
+				let control = state
+					.gamma_control_manager
+					.get_gamma_control(&output, handle, ());
+				let intermediate = GammaControlIntermediate {
+					output,
+					output_registry_name: name,
+					output_name: None,
+					output_description: None,
+					control,
+				};
+				state.intermediates.push(intermediate);
+			}
+			wl_registry::Event::GlobalRemove { name } => {
+				state
+					.intermediates
+					.retain(|intermediate| intermediate.output_registry_name != name);
+				state.controls.retain(|(id, _control)| *id != name);
+				_ = state.event_send.send(Event::RemoveOutput { id: name });
+			}
+			_ => {}
+		}
+	}
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for Helper {
+	fn event(
+		state: &mut Self,
+		proxy: &wl_output::WlOutput,
+		event: wl_output::Event,
+		_data: &(),
+		_conn: &Connection,
+		_handle: &QueueHandle<Self>,
+	) {
+		let Some(intermediate) = state
+			.intermediates
+			.iter_mut()
+			.find(|intermediate| intermediate.output == *proxy)
+		else {
+			return;
+		};
+		match event {
+			wl_output::Event::Name { name } => intermediate.output_name = Some(name.into()),
+			wl_output::Event::Description { description } => {
+				intermediate.output_description = Some(description.into());
+			}
+			_ => {}
+		}
+	}
+}
+
+delegate_noop!(Helper: ignore zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1);
+
+impl Dispatch<zwlr_gamma_control_v1::ZwlrGammaControlV1, ()> for Helper {
+	fn event(
+		state: &mut Self,
+		proxy: &zwlr_gamma_control_v1::ZwlrGammaControlV1,
+		event: zwlr_gamma_control_v1::Event,
+		_data: &(),
+		_conn: &Connection,
+		_handle: &QueueHandle<Self>,
+	) {
+		let Some(intermediate) = state
+			.intermediates
+			.take_if(|intermediate| intermediate.control == *proxy)
+		else {
+			return;
+		};
+		match event {
+			zwlr_gamma_control_v1::Event::GammaSize { size: ramp_size } => {
+				// Compositors may not send a description, so fall back to the name.
+				let description = intermediate
+					.output_description
+					.or_else(|| intermediate.output_name.clone())
+					.unwrap_or_default();
+				let info = OutputInfo {
+					id: intermediate.output_registry_name,
+					name: intermediate.output_name,
+					description,
+					ramp_size: ramp_size as usize,
+				};
+				state
+					.controls
+					.push((intermediate.output_registry_name, intermediate.control));
+				_ = state.event_send.send(Event::AddOutput(info));
+			}
+			zwlr_gamma_control_v1::Event::Failed => {
+				let output = intermediate
+					.output_description
+					.or(intermediate.output_name)
+					.map_or_else(|| "(unknown)".into(), |output| format!("{output:?}"));
+				if state.enumerated {
+					tracing::warn!(output, "could not control gamma, ignoring the output");
+					intermediate.control.destroy();
+				} else {
+					_ = state
+						.event_send
+						.send(Event::Fatal(Error::GammaControlFailed { output }));
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+pub struct Backend {
+	queue: EventQueue<Helper>,
+	helper: Helper,
+}
+
+impl Backend {
+	/// Fails if the compositor does not support `zwlr_gamma_control_manager_v1`.
+	pub fn new(event_send: EventSender, connection: &Connection) -> Result<Self, Error> {
+		let mut queue = connection.new_event_queue();
+		let handle = queue.handle();
+
+		let (_, gamma_control_manager) = get_proxy(connection, ZWLR_GAMMA_CONTROL_MANAGER_V1_VERSION)?
+			.ok_or(Error::MissingProtocol("zwlr_gamma_control_manager_v1"))?;
+		let _registry = connection.display().get_registry(&handle, ());
+
+		let mut helper = Helper {
+			gamma_control_manager,
+			event_send,
+			intermediates: Vec::new(),
+			controls: Vec::new(),
+			enumerated: false,
+		};
+		// The first roundtrip binds the outputs that exist at startup, and the second gets their gamma sizes.
+		queue.roundtrip(&mut helper)?;
+		queue.roundtrip(&mut helper)?;
+		helper.enumerated = true;
+		_ = helper.event_send.send(Event::OutputsEnumerated);
+		Ok(Self { queue, helper })
+	}
+}
+
+impl GammaBackend for Backend {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		self.queue.dispatch_pending(&mut self.helper)?;
+		Ok(())
+	}
+
+	fn set_gamma(&mut self, output: OutputId, ramps: &Ramps) -> Result<(), Error> {
+		let Some((_, control)) = self.helper.controls.iter().find(|(id, _)| *id == output) else {
+			return Ok(());
+		};
+		let mut ramps_fd: File = memfd_create(cstr!("gamma-ramps"), MemFdCreateFlag::MFD_CLOEXEC)
+			.map_err(|errno| Error::io("could not create the gamma ramp file")(errno.into()))?
+			.into();
+		ramps_fd
+			.write_all(ramps.as_bytes())
+			.and_then(|()| ramps_fd.seek(SeekFrom::Start(0)))
+			.map_err(Error::io("could not write the gamma ramp file"))?;
+		control.set_gamma(ramps_fd.as_fd());
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self.queue.roundtrip(&mut self.helper)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::Backend;
+	use crate::backend::{GammaBackend as _, Output, OutputInfo};
+	use crate::color::{Config, Ramps};
+	use crate::error::Error;
+	use crate::event_loop::EventLoop;
+	use crate::test_compositor::{OutputConfig, TestCompositor};
+	use crate::Event;
+
+	/// Returns the events that the backend sent so far.
+	fn drain(event_loop: &mut EventLoop, backend: &mut Backend) -> Vec<Event> {
+		// New outputs are bound in the first roundtrip, and report their gamma size in the second.
+		backend.flush().unwrap();
+		backend.flush().unwrap();
+		std::iter::from_fn(|| {
+			// Without a timeout, the loop only returns events that were already received.
+			match event_loop.next(backend, Some(Duration::ZERO)).unwrap() {
+				Event::Update => None,
+				event => Some(event),
+			}
+		})
+		.collect()
+	}
+
+	fn start(compositor: &TestCompositor) -> (EventLoop, Backend) {
+		let connection = compositor.connect();
+		let event_loop = EventLoop::new(None).unwrap();
+		let backend = Backend::new(event_loop.sender(), &connection).unwrap();
+		(event_loop, backend)
+	}
+
+	fn added(event: &Event) -> &OutputInfo {
+		match event {
+			Event::AddOutput(info) => info,
+			event => panic!("expected AddOutput, got {event:?}"),
+		}
+	}
+
+	#[test]
+	fn enumerates_outputs() {
+		let compositor = TestCompositor::start();
+		compositor.add_output(OutputConfig::new("DP-1").with_description("Dell U2720Q"));
+		compositor.add_output(OutputConfig::new("HDMI-A-1").with_gamma_size(1024));
+		let (mut event_loop, mut backend) = start(&compositor);
+
+		let events = drain(&mut event_loop, &mut backend);
+		assert_eq!(events.len(), 3, "{events:?}");
+		let first = added(&events[0]);
+		assert_eq!(first.name.as_deref(), Some("DP-1"));
+		assert_eq!(&*first.description, "Dell U2720Q");
+		assert_eq!(first.ramp_size, 256);
+		let second = added(&events[1]);
+		assert_eq!(second.name.as_deref(), Some("HDMI-A-1"));
+		assert_eq!(second.ramp_size, 1024);
+		assert!(matches!(events[2], Event::OutputsEnumerated));
+	}
+
+	#[test]
+	fn missing_description_falls_back_to_name() {
+		let compositor = TestCompositor::start();
+		compositor.add_output(OutputConfig::new("eDP-1"));
+		let (mut event_loop, mut backend) = start(&compositor);
+
+		let events = drain(&mut event_loop, &mut backend);
+		assert_eq!(&*added(&events[0]).description, "eDP-1");
+	}
+
+	#[test]
+	fn missing_protocol() {
+		let compositor = TestCompositor::start_without_gamma_control();
+		let connection = compositor.connect();
+		let event_loop = EventLoop::new(None).unwrap();
+		let result = Backend::new(event_loop.sender(), &connection);
+		assert!(matches!(
+			result,
+			Err(Error::MissingProtocol("zwlr_gamma_control_manager_v1"))
+		));
+	}
+
+	#[test]
+	fn hotplug() {
+		let compositor = TestCompositor::start();
+		let (mut event_loop, mut backend) = start(&compositor);
+		let events = drain(&mut event_loop, &mut backend);
+		assert!(
+			matches!(events[..], [Event::OutputsEnumerated]),
+			"{events:?}"
+		);
+
+		let output = compositor.add_output(OutputConfig::new("DP-2"));
+		let events = drain(&mut event_loop, &mut backend);
+		assert_eq!(events.len(), 1, "{events:?}");
+		let id = added(&events[0]).id;
+
+		compositor.remove_output(output);
+		let events = drain(&mut event_loop, &mut backend);
+		assert!(
+			matches!(events[..], [Event::RemoveOutput { id: removed }] if removed == id),
+			"{events:?}"
+		);
+	}
+
+	#[test]
+	fn failed_gamma_control() {
+		let compositor = TestCompositor::start();
+		compositor.add_output(OutputConfig::new("DP-1").failing());
+		let (mut event_loop, mut backend) = start(&compositor);
+
+		let events = drain(&mut event_loop, &mut backend);
+		assert!(
+			matches!(&events[..], [Event::Fatal(Error::GammaControlFailed { output }), Event::OutputsEnumerated] if output == "\"DP-1\""),
+			"{events:?}"
+		);
+	}
+
+	#[test]
+	fn failed_gamma_control_after_hotplug() {
+		let compositor = TestCompositor::start();
+		compositor.add_output(OutputConfig::new("DP-1"));
+		let (mut event_loop, mut backend) = start(&compositor);
+		drain(&mut event_loop, &mut backend);
+
+		compositor.add_output(OutputConfig::new("DP-2").failing());
+		let events = drain(&mut event_loop, &mut backend);
+		assert!(events.is_empty(), "{events:?}");
+		// The other outputs keep working.
+		compositor.add_output(OutputConfig::new("DP-3"));
+		let events = drain(&mut event_loop, &mut backend);
+		assert_eq!(added(&events[0]).name.as_deref(), Some("DP-3"));
+	}
+
+	#[test]
+	fn set_gamma() {
+		let compositor = TestCompositor::start();
+		let test_output = compositor.add_output(OutputConfig::new("DP-1"));
+		let (mut event_loop, mut backend) = start(&compositor);
+		let Event::AddOutput(info) = drain(&mut event_loop, &mut backend).remove(0) else {
+			panic!("expected AddOutput");
+		};
+		let mut output = Output::new(info);
+
+		let config = Config::new(3500, 0.8).unwrap();
+		output.set_gamma(&mut backend, config).unwrap();
+		backend.flush().unwrap();
+
+		let mut expected = Ramps::new(256);
+		config.generate_ramps(&mut expected, None);
+		assert_eq!(
+			compositor.received_gamma(test_output),
+			[expected.as_bytes()]
+		);
+	}
+
+	#[test]
+	fn set_gamma_on_removed_output() {
+		let compositor = TestCompositor::start();
+		let test_output = compositor.add_output(OutputConfig::new("DP-1"));
+		let (mut event_loop, mut backend) = start(&compositor);
+		let id = added(&drain(&mut event_loop, &mut backend)[0]).id;
+
+		compositor.remove_output(test_output);
+		drain(&mut event_loop, &mut backend);
+		backend.set_gamma(id, &Ramps::new(256)).unwrap();
+		backend.flush().unwrap();
+		assert!(compositor.received_gamma(test_output).is_empty());
+	}
+}
@@ -0,0 +1,364 @@
+//! The `x11` gamma backend, using the `RandR` extension.
+
+use std::os::fd::{AsFd as _, BorrowedFd};
+
+use x11rb::connection::{Connection as _, RequestConnection as _};
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ConnectionExt as _, Window};
+use x11rb::protocol::Event as X11Event;
+use x11rb::rust_connection::RustConnection;
+
+use crate::backend::{GammaBackend, OutputId, OutputInfo};
+use crate::color::Ramps;
+use crate::error::Error;
+use crate::event_loop::EventSender;
+use crate::Event;
+
+/// Version 1.3 added `GetScreenResourcesCurrent`, which does not make the server probe for new outputs.
+const RANDR_VERSION: (u32, u32) = (1, 3);
+
+struct Crtc {
+	id: randr::Crtc,
+	/// The name of the CRTC's first output.
+	name: Option<Box<str>>,
+	ramp_size: usize,
+	/// The gamma from before we changed it, which is restored on exit.
+	original: randr::GetCrtcGammaReply,
+}
+
+pub struct Backend {
+	connection: RustConnection,
+	root: Window,
+	event_send: EventSender,
+	/// The enabled CRTCs.
+	crtcs: Vec<Crtc>,
+}
+
+impl Backend {
+	/// Connects to the X server given by `DISPLAY`.
+	pub fn new(event_send: EventSender) -> Result<Self, Error> {
+		Self::connect(event_send, None)
+	}
+
+	fn connect(event_send: EventSender, display: Option<&str>) -> Result<Self, Error> {
+		let (connection, screen) = RustConnection::connect(display).map_err(Error::X11Connect)?;
+		let root = connection.setup().roots[screen].root;
+
+		if connection
+			.extension_information(randr::X11_EXTENSION_NAME)?
+			.is_none()
+		{
+			return Err(Error::MissingProtocol("RandR"));
+		}
+		let version = connection
+			.randr_query_version(RANDR_VERSION.0, RANDR_VERSION.1)?
+			.reply()?;
+		if (version.major_version, version.minor_version) < RANDR_VERSION {
+			return Err(Error::MissingProtocol("RandR 1.3"));
+		}
+		// Screen changes cover resolution changes, and CRTC and output changes cover hotplugging.
+		connection.randr_select_input(
+			root,
+			randr::NotifyMask::SCREEN_CHANGE
+				| randr::NotifyMask::CRTC_CHANGE
+				| randr::NotifyMask::OUTPUT_CHANGE,
+		)?;
+
+		let mut backend = Self {
+			connection,
+			root,
+			event_send,
+			crtcs: Vec::new(),
+		};
+		backend.update_crtcs()?;
+		backend.process_events()?;
+		_ = backend.event_send.send(Event::OutputsEnumerated);
+		Ok(backend)
+	}
+
+	/// Handles the events that x11rb has already read from the connection.
+	///
+	/// x11rb reads events while waiting for replies, and those do not make the connection readable again,
+	/// so this must be called after every request with a reply.
+	fn process_events(&mut self) -> Result<(), Error> {
+		loop {
+			let mut changed = false;
+			while let Some(event) = self.connection.poll_for_event()? {
+				changed |= matches!(
+					event,
+					X11Event::RandrScreenChangeNotify(_) | X11Event::RandrNotify(_)
+				);
+			}
+			if !changed {
+				return Ok(());
+			}
+			// This waits for replies, during which more events can arrive.
+			self.update_crtcs()?;
+		}
+	}
+
+	/// Enumerates the enabled CRTCs, and sends `Event::RemoveOutput` and `Event::AddOutput` for the ones that changed.
+	fn update_crtcs(&mut self) -> Result<(), Error> {
+		let resources = self
+			.connection
+			.randr_get_screen_resources_current(self.root)?
+			.reply()?;
+		let mut old_crtcs = std::mem::take(&mut self.crtcs);
+		for id in resources.crtcs {
+			let info = self
+				.connection
+				.randr_get_crtc_info(id, resources.config_timestamp)?
+				.reply()?;
+			if info.mode == 0 {
+				// Disabled.
+				continue;
+			}
+			let name = match info.outputs.first() {
+				Some(&output) => {
+					let output = self
+						.connection
+						.randr_get_output_info(output, resources.config_timestamp)?
+						.reply()?;
+					Some(String::from_utf8_lossy(&output.name).into())
+				}
+				None => None,
+			};
+			let ramp_size = self
+				.connection
+				.randr_get_crtc_gamma_size(id)?
+				.reply()?
+				.size
+				.into();
+			if ramp_size == 0 {
+				tracing::debug!(id, ?name, "CRTC does not support gamma");
+				continue;
+			}
+
+			let old = old_crtcs
+				.iter()
+				.position(|crtc| crtc.id == id)
+				.map(|index| old_crtcs.swap_remove(index));
+			let original = match old {
+				Some(old) if old.name == name && old.ramp_size == ramp_size => {
+					self.crtcs.push(old);
+					continue;
+				}
+				// The CRTC now drives a different output, which is removed and added again.
+				Some(old) => {
+					_ = self.event_send.send(Event::RemoveOutput { id });
+					if old.ramp_size == ramp_size {
+						old.original
+					} else {
+						self.connection.randr_get_crtc_gamma(id)?.reply()?
+					}
+				}
+				None => self.connection.randr_get_crtc_gamma(id)?.reply()?,
+			};
+			let description = name.clone().unwrap_or_else(|| format!("CRTC {id}").into());
+			_ = self.event_send.send(Event::AddOutput(OutputInfo {
+				id,
+				name: name.clone(),
+				description,
+				ramp_size,
+			}));
+			self.crtcs.push(Crtc {
+				id,
+				name,
+				ramp_size,
+				original,
+			});
+		}
+
+		for crtc in old_crtcs {
+			_ = self.event_send.send(Event::RemoveOutput { id: crtc.id });
+		}
+		Ok(())
+	}
+}
+
+impl GammaBackend for Backend {
+	fn dispatch_pending(&mut self) -> Result<(), Error> {
+		self.process_events()
+	}
+
+	fn fd(&self) -> Option<BorrowedFd<'_>> {
+		Some(self.connection.stream().as_fd())
+	}
+
+	fn set_gamma(&mut self, output: OutputId, ramps: &Ramps) -> Result<(), Error> {
+		if self.crtcs.iter().all(|crtc| crtc.id != output) {
+			return Ok(());
+		}
+		let [red, green, blue] = ramps.channels();
+		self
+			.connection
+			.randr_set_crtc_gamma(output, red, green, blue)?;
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		// Any request with a reply works as a roundtrip.
+		self.connection.get_input_focus()?.reply()?;
+		self.process_events()
+	}
+}
+
+/// Unlike Wayland compositors, the X server keeps the gamma after we disconnect, so it has to be restored.
+impl Drop for Backend {
+	fn drop(&mut self) {
+		for crtc in &self.crtcs {
+			let original = &crtc.original;
+			_ = self.connection.randr_set_crtc_gamma(
+				crtc.id,
+				&original.red,
+				&original.green,
+				&original.blue,
+			);
+		}
+		_ = self.connection.flush();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{BufRead as _, BufReader};
+	use std::process::{Child, Command, Stdio};
+
+	use x11rb::connection::Connection as _;
+	use x11rb::protocol::randr::{self, ConnectionExt as _};
+	use x11rb::rust_connection::RustConnection;
+
+	use super::Backend;
+	use crate::backend::{GammaBackend as _, OutputInfo};
+	use crate::color::{Config, Ramps};
+	use crate::event_loop::EventSender;
+	use crate::Event;
+
+	/// A virtual X server for the duration of a test.
+	struct Xvfb {
+		child: Child,
+		display: String,
+	}
+
+	impl Xvfb {
+		fn start() -> Self {
+			let mut child = Command::new("Xvfb")
+				.args(["-displayfd", "1", "-nolisten", "tcp"])
+				.stdout(Stdio::piped())
+				.stderr(Stdio::null())
+				.spawn()
+				.expect("could not start Xvfb");
+			// Xvfb picks a free display and prints its number once it accepts connections.
+			let mut number = String::new();
+			BufReader::new(child.stdout.take().unwrap())
+				.read_line(&mut number)
+				.unwrap();
+			Self {
+				child,
+				display: format!(":{}", number.trim()),
+			}
+		}
+
+		fn connect(&self) -> RustConnection {
+			RustConnection::connect(Some(&self.display)).unwrap().0
+		}
+	}
+
+	impl Drop for Xvfb {
+		fn drop(&mut self) {
+			_ = self.child.kill();
+			_ = self.child.wait();
+		}
+	}
+
+	fn start(xvfb: &Xvfb) -> (Backend, Vec<OutputInfo>, std::sync::mpsc::Receiver<Event>) {
+		let (event_send, event_recv) = EventSender::channel();
+		let backend = Backend::connect(event_send, Some(&xvfb.display)).unwrap();
+		let mut outputs = Vec::new();
+		loop {
+			match event_recv.try_recv().unwrap() {
+				Event::AddOutput(info) => outputs.push(info),
+				Event::OutputsEnumerated => break,
+				event => panic!("unexpected event {event:?}"),
+			}
+		}
+		(backend, outputs, event_recv)
+	}
+
+	fn crtc_gamma(connection: &RustConnection, crtc: randr::Crtc) -> [Vec<u16>; 3] {
+		let gamma = connection
+			.randr_get_crtc_gamma(crtc)
+			.unwrap()
+			.reply()
+			.unwrap();
+		[gamma.red, gamma.green, gamma.blue]
+	}
+
+	#[test]
+	#[ignore = "needs Xvfb, run with --include-ignored"]
+	fn set_and_restore_gamma() {
+		let xvfb = Xvfb::start();
+		let (mut backend, outputs, _event_recv) = start(&xvfb);
+		assert!(!outputs.is_empty());
+		let other = xvfb.connect();
+		let originals = outputs
+			.iter()
+			.map(|output| crtc_gamma(&other, output.id))
+			.collect::<Vec<_>>();
+
+		for output in &outputs {
+			let mut ramps = Ramps::new(output.ramp_size);
+			Config::new(3000, 0.5)
+				.unwrap()
+				.generate_ramps(&mut ramps, None);
+			backend.set_gamma(output.id, &ramps).unwrap();
+			backend.flush().unwrap();
+			let gamma = crtc_gamma(&other, output.id);
+			assert_eq!(gamma.each_ref().map(Vec::as_slice), ramps.channels());
+		}
+
+		drop(backend);
+		for (output, original) in outputs.iter().zip(originals) {
+			assert_eq!(crtc_gamma(&other, output.id), original);
+		}
+	}
+
+	#[test]
+	#[ignore = "needs Xvfb, run with --include-ignored"]
+	fn events_read_while_waiting_for_replies() {
+		let xvfb = Xvfb::start();
+		let (mut backend, outputs, event_recv) = start(&xvfb);
+		let id = outputs[0].id;
+
+		// Disable the CRTC from another client, and wait until the server has done so.
+		let other = xvfb.connect();
+		let root = other.setup().roots[0].root;
+		let resources = other
+			.randr_get_screen_resources_current(root)
+			.unwrap()
+			.reply()
+			.unwrap();
+		other
+			.randr_set_crtc_config(
+				id,
+				resources.timestamp,
+				resources.config_timestamp,
+				0,
+				0,
+				0,
+				randr::Rotation::ROTATE0,
+				&[],
+			)
+			.unwrap()
+			.reply()
+			.unwrap();
+
+		// The notification is read while waiting for the reply in `flush`,
+		// after which the connection is not readable anymore, so it must be handled right away.
+		backend.flush().unwrap();
+		assert!(matches!(
+			event_recv.try_recv(),
+			Ok(Event::RemoveOutput { id: removed }) if removed == id
+		));
+	}
+}